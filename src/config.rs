@@ -1,54 +1,298 @@
 use std::path::PathBuf;
 
+use chrono::NaiveDate;
 use clap::Clap;
+use yaml_rust::Yaml;
 
+/// Options given on the command line
+/// Options also available in ontime.yml don't have defaults here, so it's known whether they were given, see settings.rs
 #[derive(Clap, Debug)]
 #[clap(version = "1.0", author = "Korneliusz W.")]
 pub struct Config {
-    #[clap(short, long, about="Maximum time distance between current time and unexecuted event, in which the pending event will be executed (in minutes)", default_value = "90")]
-    pub distance: i64,
+    #[clap(short, long, about="Maximum time distance between current time and unexecuted event, in which the pending event will be executed (in minutes) [default: 90]")]
+    pub distance: Option<i64>,
     #[clap(long, about="Maximum time distance between current time and unexecuted start event, in which the pending event will be executed (in minutes)")]
     pub distance_start: Option<i64>,
     #[clap(long, about="Maximum time distance between current time and unexecuted end event, in which the pending event will be executed (in minutes)")]
     pub distance_end: Option<i64>,
+    #[clap(
+        long,
+        about = "Behaviour when execution fails, comma separated \"retry\" (on a next check) or \"none\" [default: none]"
+    )]
+    pub on_fail: Option<OnFail>,
     #[clap(
         short,
         long,
-        default_value = "0",
-        about = "Flags specyfing behaviour when execution fails, 0 - nothing changes, 1 - retry on a next check"
+        hidden = true,
+        about = "Deprecated flags of --on-fail, 0 - nothing changes, 1 - retry on a next check"
+    )]
+    pub fail: Option<u32>,
+    #[clap(
+        long,
+        about = "Maximum number of retries of a failed execution, when retrying is enabled [default: 3]"
+    )]
+    pub retries: Option<u32>,
+    #[clap(
+        long,
+        about = "Time to wait before the first retry of a failed execution (in minutes) [default: 1]"
     )]
-    pub fail: u32,
+    pub retry_delay: Option<i64>,
     #[clap(
         long,
-        default_value = "1",
-        about = "Every return code greater than or equal this argument will be considered failed"
+        about = "Multiplier applied to the retry delay after every failed retry (1 disables the backoff) [default: 2]"
     )]
-    pub fail_on_code: i32,
+    pub retry_backoff: Option<i64>,
+    #[clap(
+        long,
+        about = "Command run after successful execution, unless the event sets its own on_success"
+    )]
+    pub on_success: Option<String>,
+    #[clap(
+        long,
+        about = "Command run after failed execution, unless the event sets its own on_failure"
+    )]
+    pub on_failure: Option<String>,
+    #[clap(
+        long,
+        about = "Every return code greater than this argument will be considered failed, unless the event sets success_codes or failure_codes [default: 1]"
+    )]
+    pub fail_on_code: Option<i32>,
+    #[clap(
+        long,
+        about = "Consider execution killed by a signal successful, instead of failed"
+    )]
+    pub signal_success: bool,
     #[clap(
         short,
         long,
-        default_value = "1",
-        about = "Flags specyfing what to pipe: 1 - stderr 2 - stdout"
+        about = "Streams piped to --pipe-to, comma separated \"stdout\", \"stderr\" or \"none\" [default: stdout]"
     )]
-    pub pipe: u32,
+    pub pipe: Option<PipeArg>,
     #[clap(
         long,
-        default_value = "stderr",
-        about = "Pipes to \"stderr\", \"stdout\", \"none\" or \"file\" (requires --file argument)"
+        about = "Pipes to \"stderr\", \"stdout\", \"none\" or \"file\" (requires --file argument) [default: stderr]"
     )]
-    pub pipe_to: PipeTo,
+    pub pipe_to: Option<PipeTo>,
     #[clap(
         long,
         about = "Pipes to the given file, only works when --pipe-to is set to a file"
     )]
     pub file: Option<PathBuf>,
+    #[clap(
+        long,
+        about = "Destination of standard output, \"none\", \"inherit\", \"stdout\", \"stderr\", \"syslog\", \"combined\" (with standard error) or a file path containing a slash, takes precedence over --pipe"
+    )]
+    pub stdout_to: Option<Destination>,
+    #[clap(
+        long,
+        about = "Destination of standard error, same values as --stdout-to, takes precedence over --pipe"
+    )]
+    pub stderr_to: Option<Destination>,
+    #[clap(
+        long,
+        default_value = "text",
+        about = "Format of the logs, \"text\" or \"json\" (one object per line, with fields of scheduler decisions)"
+    )]
+    pub log_format: LogFormat,
+    #[clap(
+        long,
+        default_value = "stderr",
+        about = "Logs to \"stderr\" or \"syslog\" (journald if it's running, else /dev/log in RFC 5424 format)"
+    )]
+    pub log_to: LogTo,
+    #[clap(
+        long,
+        about = "Structured data ID of RFC 5424 syslog messages, name@<private enterprise number>, decision fields are sent as structured data only when it's given"
+    )]
+    pub syslog_sd_id: Option<SdId>,
+    #[clap(
+        long,
+        about = "Directory with plans, used in place of $XDG_CONFIG_HOME/ontime, $XDG_CONFIG_DIRS/ontime and /etc/ontime"
+    )]
+    pub config_dir: Option<PathBuf>,
+    #[clap(
+        long,
+        about = "Path of the cache, locked through <path>.pid next to it [default: $XDG_CACHE_HOME/ontime/ontime.cache]"
+    )]
+    pub cache: Option<PathBuf>,
+    #[clap(
+        long,
+        about = "Directory keeping the history [default: $XDG_STATE_HOME/ontime]"
+    )]
+    pub state_dir: Option<PathBuf>,
+    #[clap(
+        long,
+        about = "Runs as a system daemon, keeping the cache and the state in /var/lib/ontime unless set otherwise"
+    )]
+    pub system: bool,
+    #[clap(
+        long,
+        about = "Waits for another running instance to exit, instead of exiting immediately"
+    )]
+    pub wait: bool,
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+#[derive(Clap, Debug)]
+pub enum Command {
+    #[clap(about = "Prints history of executions")]
+    History(History),
+}
+#[derive(Clap, Debug)]
+pub struct History {
+    #[clap(long, about = "Prints only executions since the given date (in YYYY-MM-DD format)")]
+    pub since: Option<NaiveDate>,
+    #[clap(
+        long,
+        about = "Prints only executions of the given event, by its id (checksum for events without one) or by its command, e.g. bell.sh"
+    )]
+    pub event: Option<String>,
 }
-pub const FAIL_RETRY : u32 = 0x1;
-pub const PIPE_FROM_NONE : u32 = 0x0;
-pub const PIPE_FROM_STDOUT : u32 = 0x1;
-pub const PIPE_FROM_STDERR : u32 = 0x2;
 
-#[derive(Debug)]
+/// Destination of a stream of the executed command's output
+/// Any value other than a keyword containing a slash is a file path, e.g. ./out.log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Destination {
+    NONE,
+    // Same stream of ontime itself
+    INHERIT,
+    STDOUT,
+    STDERR,
+    FILE(PathBuf),
+    SYSLOG,
+    // Wherever the other stream goes
+    COMBINED,
+}
+impl std::str::FromStr for Destination {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::NONE),
+            "inherit" => Ok(Self::INHERIT),
+            "stdout" => Ok(Self::STDOUT),
+            "stderr" => Ok(Self::STDERR),
+            "syslog" => Ok(Self::SYSLOG),
+            "combined" => Ok(Self::COMBINED),
+            x if x.contains('/') => Ok(Self::FILE(PathBuf::from(x))),
+            _ => Err(format!(
+                "Value {} not allowed, expected none, inherit, stdout, stderr, syslog, combined or a path",
+                s
+            )),
+        }
+    }
+}
+
+/// Flag of the deprecated --fail option
+const FAIL_RETRY: u32 = 0x1;
+
+/// Behaviour when execution fails
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OnFail {
+    // Retry on a next check
+    pub retry: bool,
+}
+impl OnFail {
+    /// Reads flags of the deprecated --fail option
+    pub fn from_flags(flags: u32) -> Self {
+        Self {
+            retry: (flags & FAIL_RETRY) > 0,
+        }
+    }
+}
+impl std::str::FromStr for OnFail {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut on_fail = Self::default();
+        for name in s.split(',').map(str::trim) {
+            match name {
+                "retry" => on_fail.retry = true,
+                "none" => (),
+                _ => return Err(format!("Value {} not allowed, expected retry or none", name)),
+            }
+        }
+        Ok(on_fail)
+    }
+}
+/// Streams of the executed command piped to --pipe-to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Pipe {
+    pub stdout: bool,
+    pub stderr: bool,
+}
+impl Pipe {
+    pub const STDOUT: Self = Self {
+        stdout: true,
+        stderr: false,
+    };
+    /// Reads flags of the deprecated numeric --pipe
+    pub fn from_flags(flags: u32) -> Self {
+        Self {
+            stdout: (flags & PIPE_STDOUT) > 0,
+            stderr: (flags & PIPE_STDERR) > 0,
+        }
+    }
+}
+impl std::str::FromStr for Pipe {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut pipe = Self::default();
+        for name in s.split(',').map(str::trim) {
+            match name {
+                "stdout" => pipe.stdout = true,
+                "stderr" => pipe.stderr = true,
+                "none" => (),
+                _ => {
+                    return Err(format!(
+                        "Value {} not allowed, expected stdout, stderr or none",
+                        name
+                    ))
+                }
+            }
+        }
+        Ok(pipe)
+    }
+}
+/// Flags of the deprecated numeric --pipe
+const PIPE_STDOUT: u32 = 0x1;
+const PIPE_STDERR: u32 = 0x2;
+
+/// Value of --pipe, which still accepts the deprecated flags (1 - stdout, 2 - stderr, 3 - both)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PipeArg {
+    pub pipe: Pipe,
+    // Given as the deprecated flags
+    pub deprecated: bool,
+}
+impl std::str::FromStr for PipeArg {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().parse::<u32>() {
+            Ok(flags) => Ok(Self {
+                pipe: Pipe::from_flags(flags),
+                deprecated: true,
+            }),
+            Err(_) => Ok(Self {
+                pipe: s.parse()?,
+                deprecated: false,
+            }),
+        }
+    }
+}
+/// Returns value of a yaml attribute holding names, as it would be given on the command line
+/// Names can be given as a comma separated string or a list, e.g. [stdout, stderr]
+pub fn yaml_names(yaml: &Yaml) -> Option<String> {
+    match yaml {
+        Yaml::String(x) => Some(x.to_owned()),
+        Yaml::Array(x) => x
+            .iter()
+            .map(|x| x.as_str())
+            .collect::<Option<Vec<&str>>>()
+            .map(|x| x.join(",")),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum PipeTo {
     STDOUT,
     STDERR,
@@ -67,3 +311,60 @@ impl std::str::FromStr for PipeTo {
         }
     }
 }
+
+#[derive(Debug)]
+pub enum LogFormat {
+    TEXT,
+    JSON,
+}
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::TEXT),
+            "json" => Ok(Self::JSON),
+            _ => Err("Value not allowed".into()),
+        }
+    }
+}
+/// Private structured data ID, which can carry parameters of any name (RFC 5424, section 7.2.2)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SdId(pub String);
+impl std::str::FromStr for SdId {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let valid = match s.split_once('@') {
+            Some((name, number)) => {
+                !name.is_empty()
+                    && name.chars().all(|x| x.is_ascii_graphic() && !"=]\"@".contains(x))
+                    && number
+                        .split('.')
+                        .all(|x| !x.is_empty() && x.chars().all(|x| x.is_ascii_digit()))
+                    && s.len() <= 32
+            }
+            None => false,
+        };
+        match valid {
+            true => Ok(Self(s.to_owned())),
+            false => Err(format!(
+                "Value {} not allowed, expected name@<private enterprise number>",
+                s
+            )),
+        }
+    }
+}
+#[derive(Debug)]
+pub enum LogTo {
+    STDERR,
+    SYSLOG,
+}
+impl std::str::FromStr for LogTo {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stderr" => Ok(Self::STDERR),
+            "syslog" => Ok(Self::SYSLOG),
+            _ => Err("Value not allowed".into()),
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use crate::directory::{is_yaml_file_name, DROP_IN_DIR};
+use nix::errno::Errno;
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+use std::path::PathBuf;
+
+/// Watches directories with plans and their drop-in directories for added, edited and removed yaml files
+/// Only directories existing when the watcher is created are watched
+#[derive(Debug)]
+pub struct ConfigWatcher {
+    inotify: Inotify,
+}
+impl ConfigWatcher {
+    pub fn new(dirs: &[PathBuf]) -> nix::Result<Self> {
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+        // Editors often save by renaming a temporary file, so moves are watched along with writes
+        let flags = AddWatchFlags::IN_CLOSE_WRITE
+            | AddWatchFlags::IN_DELETE
+            | AddWatchFlags::IN_MOVED_TO
+            | AddWatchFlags::IN_MOVED_FROM;
+        let drop_in_dirs = dirs.iter().map(|x| x.join(DROP_IN_DIR)).collect::<Vec<PathBuf>>();
+        for dir in dirs.iter().chain(drop_in_dirs.iter()).filter(|x| x.is_dir()) {
+            debug!("Watching {:?}", dir);
+            inotify.add_watch(dir, flags)?;
+        }
+        Ok(Self { inotify })
+    }
+    /// Returns whether any yaml file was changed since the last call (or events were dropped), without blocking
+    pub fn changed(&self) -> nix::Result<bool> {
+        let mut changed = false;
+        loop {
+            let events = match self.inotify.read_events() {
+                Ok(x) => x,
+                // No more events are queued
+                Err(nix::Error::Sys(Errno::EAGAIN)) => return Ok(changed),
+                Err(err) => return Err(err),
+            };
+            for event in events {
+                // Events were dropped, so any file could have changed
+                if event.mask.contains(AddWatchFlags::IN_Q_OVERFLOW) {
+                    warn!("Too many changes of plans at once, reloading them all");
+                    changed = true;
+                }
+                if let Some(name) = &event.name {
+                    if is_yaml_file_name(&name.to_string_lossy()) {
+                        debug!("{:?} changed", name);
+                        changed = true;
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,60 +1,81 @@
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::{borrow::Borrow, env};
 
-pub fn read_env_dir_or_fallback_to_etc<T>(
-    env_var: &str,
-    dir: &str,
+const SYSTEM_CONFIG_DIR: &str = "/etc";
+const DEFAULT_CONFIG_DIRS: &str = "/etc/xdg";
+/// Directory with drop-in plans, inside every directory with configuration
+pub const DROP_IN_DIR: &str = "conf.d";
+
+/// Returns directories with configuration of the application, from the most to the least important
+/// $XDG_CONFIG_HOME (~/.config), every directory of $XDG_CONFIG_DIRS (/etc/xdg) and /etc
+/// Only the given directory is returned if it's set, e.g. by --config-dir
+pub fn config_dirs(dir: &str, config_dir: &Option<PathBuf>) -> Vec<PathBuf> {
+    if let Some(x) = config_dir {
+        return vec![x.to_owned()];
+    }
+    let mut dirs = Vec::new();
+    if let Some(x) = xdg_dir("XDG_CONFIG_HOME", ".config", dir) {
+        dirs.push(x);
+    }
+    let config_dirs = match env::var("XDG_CONFIG_DIRS") {
+        Ok(x) if !x.is_empty() => x,
+        _ => DEFAULT_CONFIG_DIRS.to_owned(),
+    };
+    // Relative paths are invalid according to the specification, so they're ignored
+    for path in config_dirs.split(':').map(PathBuf::from).filter(|x| x.is_absolute()) {
+        dirs.push(path.join(dir));
+    }
+    dirs.push(PathBuf::from(SYSTEM_CONFIG_DIR).join(dir));
+    dirs.dedup();
+    dirs
+}
+/// Reads content of the directories, returned by config_dirs, and their drop-in directories (conf.d)
+/// Files are merged by name, so a file in a more important directory (e.g. user's) replaces the same file in a less important one (e.g. admin's)
+/// Drop-in files are merged only with other drop-in files
+pub fn read_config_dirs<T>(
+    dirs: &Vec<PathBuf>,
     filter: Option<T>,
 ) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>>
 where
     T: Fn(fs::ReadDir) -> Vec<PathBuf>,
 {
-    let mut dir_content_wide: Option<Vec<PathBuf>> = None;
-    let found: bool = match env::var(env_var) {
-        Ok(x) => {
-            // Use closure to return booleans that will then be returned by match
-            // Normally, return statement inside match referes to a whole function
-            let closure = || {
-                let mut env_path = PathBuf::from(x);
-                env_path.push(dir);
-                if !env_path.exists() {
-                    return false;
-                };
-                debug!("Looking up {:?}", env_path);
-                let dir_content_all = fs::read_dir(env_path).unwrap();
-                let dir_content = match filter.borrow() {
-                    Some(f) => f(dir_content_all),
-                    None => read_dir_to_pathbuf(dir_content_all),
-                };
-                if dir_content.len() == 0 {
-                    debug!("No yaml files found!");
-                    return false;
-                }
-                dir_content_wide = Some(dir_content);
-                true
-            };
-            closure()
-        }
-        _ => false,
-    };
-    if !found {
-        debug!("Looking up /etc/ontime/");
-        let etc_path = PathBuf::from("/etc/ontime/");
-        if !etc_path.exists() {
-            return Err("No configuration folder, make sure you have ontime/ directory in either /etc/ or XDG_CONFIG_HOME".into());
+    let mut dir_content_wide: Vec<PathBuf> = Vec::new();
+    for path in dirs.iter().flat_map(|x| vec![x.to_owned(), x.join(DROP_IN_DIR)]) {
+        if !path.is_dir() {
+            continue;
         }
-        let dir_content_all = fs::read_dir(&etc_path)?;
-        let dir_content = match filter {
+        debug!("Looking up {:?}", path);
+        let dir_content_all = fs::read_dir(&path)?;
+        let dir_content = match filter.borrow() {
             Some(f) => f(dir_content_all),
             None => read_dir_to_pathbuf(dir_content_all),
         };
-        if dir_content.len() == 0 {
-            return Err("No yaml files found in /etc/ontime".into());
+        for file in dir_content {
+            if dir_content_wide
+                .iter()
+                .any(|x| x.file_name() == file.file_name() && is_drop_in(x) == is_drop_in(&file))
+            {
+                debug!("{:?} is overridden by a more important directory", file);
+                continue;
+            }
+            dir_content_wide.push(file);
         }
-        dir_content_wide = Some(dir_content);
     }
-    Ok(dir_content_wide.unwrap())
+    if dir_content_wide.is_empty() {
+        return Err(format!(
+            "No yaml files found, make sure you have one of {:?} directories with a plan",
+            dirs
+        )
+        .into());
+    }
+    Ok(dir_content_wide)
+}
+/// Returns whether the file is in a drop-in directory
+pub fn is_drop_in(file: &Path) -> bool {
+    file.parent()
+        .and_then(|x| x.file_name())
+        .is_some_and(|x| x == DROP_IN_DIR)
 }
 fn read_dir_to_pathbuf(dir_content: fs::ReadDir) -> Vec<PathBuf> {
     dir_content
@@ -62,24 +83,19 @@ fn read_dir_to_pathbuf(dir_content: fs::ReadDir) -> Vec<PathBuf> {
         .map(|item| item.unwrap().path())
         .collect()
 }
-pub fn find_env_dir_or_etc(
-    env_var: &str,
-    dir: &str,
-) -> Result<PathBuf, Box<dyn std::error::Error>> {
-    let mut path = PathBuf::new();
-    let found: bool = match env::var(env_var) {
-        Ok(x) => {
-            path.push(x);
-            path.push(dir);
-            path.exists()
+/// Returns the directory of the application in the XDG base directory given by env_var
+/// If the variable isn't set (or isn't an absolute path, as the specification requires), falls back to home_dir in $HOME
+pub fn xdg_dir(env_var: &str, home_dir: &str, dir: &str) -> Option<PathBuf> {
+    let mut path = match env::var_os(env_var).map(PathBuf::from) {
+        Some(x) if x.is_absolute() => x,
+        _ => {
+            let mut home = PathBuf::from(env::var_os("HOME")?);
+            home.push(home_dir);
+            home
         }
-        _ => false,
     };
-    if !found {
-        path = PathBuf::from("/etc/");
-        path.push(dir);
-    }
-    Ok(path)
+    path.push(dir);
+    Some(path)
 }
 pub fn filter_dir_content(content: fs::ReadDir) -> Vec<PathBuf> {
     return content
@@ -87,10 +103,10 @@ pub fn filter_dir_content(content: fs::ReadDir) -> Vec<PathBuf> {
         .filter(|element_res| {
             return match element_res {
                 Ok(element) => {
-                    let file_name_osstr = element.file_name();
-                    let file_name = file_name_osstr.to_str().unwrap();
-                    element.file_type().unwrap().is_file()
-                        && (file_name.ends_with(".yml") || file_name.ends_with(".yaml"))
+                    // Names which aren't valid UTF-8 are kept, so they can be reported by the plan
+                    let file_name = element.file_name();
+                    element.file_type().is_ok_and(|x| x.is_file())
+                        && is_yaml_file_name(&file_name.to_string_lossy())
                 }
                 _ => false,
             };
@@ -98,3 +114,6 @@ pub fn filter_dir_content(content: fs::ReadDir) -> Vec<PathBuf> {
         .map(|element| element.unwrap().path())
         .collect();
 }
+pub fn is_yaml_file_name(file_name: &str) -> bool {
+    file_name.ends_with(".yml") || file_name.ends_with(".yaml")
+}
@@ -68,17 +68,94 @@ impl<'a> BadTimeFormat {
     }
 }
 #[derive(Debug, Clone)]
-pub struct ExecutionError;
+pub struct BadAttributeValue {
+    attribute: String,
+    weekday: String,
+}
 
-impl fmt::Display for ExecutionError {
+impl fmt::Display for BadAttributeValue {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "Script execution failed"
+            "Value of attribute {} not allowed in {} plan",
+            self.attribute, self.weekday
         )
     }
 }
+impl error::Error for BadAttributeValue {}
+
+impl<'a> BadAttributeValue {
+    pub fn new(attribute: &'a str, weekday: &'a str) -> Self {
+        Self {
+            weekday: weekday.to_owned(),
+            attribute: attribute.to_owned(),
+        }
+    }
+}
+#[derive(Debug, Clone)]
+pub struct ExecutionError {
+    // Exit code, or 128 + signal number if killed by a signal
+    pub code: i32,
+    // Name of the signal that killed the script
+    pub signal: Option<String>,
+    // Captured standard error, empty if it wasn't captured
+    pub stderr: String,
+}
+
+impl fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.signal {
+            Some(signal) => write!(f, "Script killed by signal {}", signal),
+            None => write!(f, "Script execution failed with code {}", self.code),
+        }
+    }
+}
 
 impl error::Error for ExecutionError {}
+#[derive(Debug, Clone)]
+pub struct AlreadyRunningError {
+    // Process id read from the pidfile, empty if it couldn't be read
+    pid: String,
+}
 
+impl fmt::Display for AlreadyRunningError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.pid.is_empty() {
+            write!(f, "Another instance of ontime is already running")
+        } else {
+            write!(f, "Another instance of ontime is already running (pid {})", self.pid)
+        }
+    }
+}
+impl error::Error for AlreadyRunningError {}
+
+impl<'a> AlreadyRunningError {
+    pub fn new(pid: &'a str) -> Self {
+        Self { pid: pid.trim().to_owned() }
+    }
+}
+#[derive(Debug, Clone)]
+pub struct TemplateNotFoundError {
+    template: String,
+    weekday: String,
+}
+
+impl fmt::Display for TemplateNotFoundError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Template {} used in {} plan not found",
+            self.template, self.weekday
+        )
+    }
+}
+impl error::Error for TemplateNotFoundError {}
 
+impl<'a> TemplateNotFoundError {
+    pub fn new(template: &'a str, weekday: &'a str) -> Self {
+        Self {
+            template: template.to_owned(),
+            weekday: weekday.to_owned(),
+        }
+    }
+}
@@ -1,10 +1,24 @@
-use crate::errors::{BadTimeFormat, RequiredAttributeMissingError};
-use crate::CONFIG;
+use crate::config::{yaml_names, Destination, OnFail, Pipe, PipeTo};
+use crate::output::Output;
+use crate::errors::{BadAttributeValue, BadTimeFormat, RequiredAttributeMissingError};
+use crate::event_log::EventLog;
+use crate::exit_codes::ExitCodes;
+use crate::hooks::Hooks;
+use crate::logger::Decision;
+use crate::service::Service;
+use crate::settings;
 use chrono::{NaiveDateTime, NaiveTime};
+use log::Level;
 use std::borrow::Cow;
 use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::path::PathBuf;
+use std::str::FromStr;
 use yaml_rust::Yaml;
 
+// Longest delay of a retry in minutes, however large retry_delay and retry_backoff are
+const MAX_RETRY_DELAY: i64 = 24 * 60;
+
 #[derive(Debug, Clone, Eq)]
 pub struct Event {
     start: i64,
@@ -15,7 +29,41 @@ pub struct Event {
     pub execute_end: String,
     // Run the given string every minute, starting from start (+1) until end (-1) is reached
     pub during: Option<String>,
+    // Long-running process spawned on start and signalled on end, used in place of execute_start and execute_end
+    pub service: Option<Service>,
+    // Commands run after execution succeeds or fails
+    pub hooks: Hooks,
+    // File capturing output of executions, in place of --pipe-to
+    pub log: Option<EventLog>,
     pub executed: (bool, bool),
+    // Whether start and end were reported as skipped, because they are outside of the distance window
+    pub skipped: (bool, bool),
+    // Failed attempts of the pending (start or end) execution
+    pub attempts: u32,
+    // Timestamp before which the pending execution won't be retried
+    pub retry_at: i64,
+    // Override --retries, --retry-delay and --retry-backoff, setting retries enables retrying
+    retries: Option<u32>,
+    retry_delay: Option<i64>,
+    retry_backoff: Option<i64>,
+    // Overrides --signal-success
+    signal_success: Option<bool>,
+    // Replace --fail-on-code, codes not in success_codes or in failure_codes are considered failed
+    success_codes: Option<ExitCodes>,
+    failure_codes: Option<ExitCodes>,
+    // Override --distance, --distance-start, --distance-end, --on-fail, --fail-on-code, --pipe and --pipe-to
+    distance: Option<i64>,
+    distance_start: Option<i64>,
+    distance_end: Option<i64>,
+    on_fail: Option<OnFail>,
+    fail_on_code: Option<i32>,
+    pipe: Option<Pipe>,
+    pipe_to: Option<PipeTo>,
+    // Override --stdout-to and --stderr-to
+    stdout_to: Option<Destination>,
+    stderr_to: Option<Destination>,
+    // Explicit identity given by id (or name) attribute, used in place of the checksum
+    pub id: Option<String>,
     pub checksum: Option<String>,
 }
 impl Event {
@@ -23,6 +71,15 @@ impl Event {
         yaml_object: &Yaml,
         weekday: &Cow<'static, str>,
     ) -> Result<Self, Box<dyn std::error::Error>> {
+        let service = Service::new(yaml_object, weekday)?;
+        // Service events don't need start and end scripts, as the service itself is started and stopped
+        let required_script = |attribute: &str| -> Result<String, RequiredAttributeMissingError> {
+            match yaml_object[attribute].to_owned().into_string() {
+                Some(x) => Ok(x),
+                None if service.is_some() => Ok(String::new()),
+                None => Err(RequiredAttributeMissingError::new(attribute, weekday)),
+            }
+        };
         Ok(Event {
             start: parse_time(
                 yaml_object["start"]
@@ -36,78 +93,284 @@ impl Event {
                     .ok_or(RequiredAttributeMissingError::new("end", weekday))?,
                 weekday.as_ref(),
             )?,
-            execute_start: yaml_object["execute_start"]
-                .to_owned()
-                .into_string()
-                .ok_or(RequiredAttributeMissingError::new("execute_start", weekday))?,
+            execute_start: required_script("execute_start")?,
             during: yaml_object["during"].to_owned().into_string(),
-            execute_end: yaml_object["execute_end"]
-                .to_owned()
-                .into_string()
-                .ok_or(RequiredAttributeMissingError::new("execute_end", weekday))?,
+            execute_end: required_script("execute_end")?,
+            service,
+            hooks: Hooks::new(yaml_object),
+            log: EventLog::new(yaml_object, weekday)?,
             executed: (false, false),
+            skipped: (false, false),
+            attempts: 0,
+            retry_at: 0,
+            retries: parse_int::<u32>(yaml_object, "retries", weekday)?,
+            retry_delay: parse_int::<u32>(yaml_object, "retry_delay", weekday)?.map(i64::from),
+            retry_backoff: parse_int::<u32>(yaml_object, "retry_backoff", weekday)?.map(i64::from),
+            signal_success: parse_bool(yaml_object, "signal_success", weekday)?,
+            success_codes: ExitCodes::new(yaml_object, "success_codes", weekday)?,
+            failure_codes: ExitCodes::new(yaml_object, "failure_codes", weekday)?,
+            distance: parse_int::<u32>(yaml_object, "distance", weekday)?.map(i64::from),
+            distance_start: parse_int::<u32>(yaml_object, "distance_start", weekday)?.map(i64::from),
+            distance_end: parse_int::<u32>(yaml_object, "distance_end", weekday)?.map(i64::from),
+            on_fail: match parse_names(yaml_object, "on_fail", weekday)? {
+                Some(x) => Some(x),
+                None => parse_fail(yaml_object, weekday)?,
+            },
+            fail_on_code: parse_int::<i32>(yaml_object, "fail_on_code", weekday)?,
+            pipe: parse_names(yaml_object, "pipe", weekday)?,
+            pipe_to: match yaml_object["pipe_to"].as_str() {
+                Some(x) => Some(
+                    x.parse::<PipeTo>()
+                        .map_err(|_| BadAttributeValue::new("pipe_to", weekday))?,
+                ),
+                None => None,
+            },
+            stdout_to: parse_names(yaml_object, "stdout_to", weekday)?,
+            stderr_to: parse_names(yaml_object, "stderr_to", weekday)?,
+            id: match parse_id(yaml_object, "id", weekday)? {
+                Some(x) => Some(x),
+                None => parse_id(yaml_object, "name", weekday)?,
+            },
             checksum: None,
         })
     }
-    /// Calculate MD5 checksum of the event (from start, end, execute_start and execute_end fields)
+    /// Calculate MD5 checksum of the event (from start, end, execute_start, execute_end and during fields)
     /// Sets self.checksum to calculated checksum, in hexadecimal string format
     /// Used to later compare with cache
     pub fn calculate_checksum(&mut self) {
         let self_string = self.to_string();
         self.checksum = Some(format!("{:x}", md5::compute(self_string)));
     }
+    /// Returns the key identifying this event
+    /// Explicit id if set, so editing the event doesn't make it look new, else its checksum if calculated
+    pub fn key(&self) -> String {
+        match (&self.id, &self.checksum) {
+            (Some(x), _) => x.to_owned(),
+            (None, Some(x)) => x.to_owned(),
+            (None, None) => self.to_string(),
+        }
+    }
     /// Compares current time with times of start and end of an event
     /// Returns ExecutionType::NONE if it isn't the right time or execution had been issued previously
-    /// Returns ExeuctionType::LOOP if the event have started, haven't end yet and during field is specified.
+    /// Returns ExeuctionType::LOOP if the event have started, haven't end yet and during or run field is specified.
     /// Else returns ExecutionType::START or ExecutionType::END depending on the time
-    pub fn should_execute(&self, time_now: &i64) -> ExecutionType {
+    /// Service is stopped (ExecutionType::END) whenever its end has passed, the distance window applies only to end scripts
+    pub fn should_execute(&mut self, time_now: &i64) -> ExecutionType {
+        // Distances of the event take precedence over the global ones, even the more specific ones
+        let settings = settings::get();
         let distance_start = self.start.to_owned()
-            + (CONFIG
+            + (self
                 .distance_start
-                .or_else(|| Some(CONFIG.distance))
-                .unwrap()
+                .or(self.distance)
+                .or(settings.distance_start)
+                .unwrap_or(settings.distance)
                 * 60);
         let distance_end = self.end.to_owned()
-            + (CONFIG
+            + (self
                 .distance_end
-                .or_else(|| Some(CONFIG.distance))
-                .unwrap()
+                .or(self.distance)
+                .or(settings.distance_end)
+                .unwrap_or(settings.distance)
                 * 60);
-        if !self.executed.0 && &self.start <= time_now && &distance_start >= time_now {
-            debug!(
-                "Executing start script (timestamp {}): {}",
-                &time_now, self.execute_start
-            );
+        if !self.executed.0
+            && &self.start <= time_now
+            && &distance_start >= time_now
+            && &self.retry_at <= time_now
+        {
+            Decision::new("event_due")
+                .event(&self.key())
+                .phase(ExecutionType::START.phase())
+                .time("scheduled", self.start)
+                .time("now", *time_now)
+                .log(
+                    Level::Info,
+                    &format!(
+                        "Executing start script (timestamp {}): {}",
+                        &time_now,
+                        self.command(&ExecutionType::START)
+                    ),
+                );
             return ExecutionType::START;
         } else if self.executed.0
             && !self.executed.1
             && &self.end <= time_now
-            && &distance_end >= time_now
+            // Missed stop would leave the service running unsupervised, so it's stopped however late it is
+            && (self.service.is_some() || &distance_end >= time_now)
+            && &self.retry_at <= time_now
         {
-            debug!(
-                "Executing end script (timestamp {}): {}",
-                &time_now, self.execute_end
-            );
+            Decision::new("event_due")
+                .event(&self.key())
+                .phase(ExecutionType::END.phase())
+                .time("scheduled", self.end)
+                .time("now", *time_now)
+                .log(
+                    Level::Info,
+                    &format!(
+                        "Executing end script (timestamp {}): {}",
+                        &time_now,
+                        self.command(&ExecutionType::END)
+                    ),
+                );
             return ExecutionType::END;
         } else if self.executed.0
             && !self.executed.1
             && &self.end > time_now
-            && self.during.is_some()
+            && (self.during.is_some() || self.service.is_some())
         {
             return ExecutionType::LOOP;
         }
+        if !self.executed.0 && !self.skipped.0 && &distance_start < time_now {
+            self.skipped.0 = true;
+            self.log_skipped(ExecutionType::START, self.start, distance_start, time_now);
+        } else if self.executed.0
+            && !self.executed.1
+            && !self.skipped.1
+            && &distance_end < time_now
+        {
+            self.skipped.1 = true;
+            self.log_skipped(ExecutionType::END, self.end, distance_end, time_now);
+        }
         ExecutionType::NONE
     }
+    /// Command executed in the given phase, the command of the service for every phase of a service
+    pub fn command(&self, execution_type: &ExecutionType) -> &str {
+        match (&self.service, execution_type, &self.during) {
+            (Some(service), _, _) => &service.run,
+            (None, ExecutionType::START, _) => &self.execute_start,
+            (None, ExecutionType::END, _) => &self.execute_end,
+            (None, ExecutionType::LOOP, Some(during)) => during,
+            _ => "",
+        }
+    }
+    fn log_skipped(&self, execution_type: ExecutionType, scheduled: i64, distance: i64, time_now: &i64) {
+        Decision::new("event_skipped")
+            .event(&self.key())
+            .phase(execution_type.phase())
+            .time("scheduled", scheduled)
+            .time("window_end", distance)
+            .time("now", *time_now)
+            .log(
+                Level::Info,
+                &format!(
+                    "Skipping {} script, it's outside of the distance window",
+                    execution_type.phase()
+                ),
+            );
+    }
+    /// Registers a failed attempt of the pending execution
+    /// Returns true and delays the next attempt if the execution should be retried
+    /// Every next retry is delayed by retry_delay multiplied by retry_backoff to the power of previous retries, up to MAX_RETRY_DELAY
+    pub fn schedule_retry(&mut self, time_now: &i64, script: &str) -> bool {
+        let settings = settings::get();
+        if self.retries.is_none() && !self.on_fail.unwrap_or(settings.on_fail).retry {
+            return false;
+        }
+        let retries = self.retries.unwrap_or(settings.retries);
+        self.attempts += 1;
+        if self.attempts > retries {
+            error!("Giving up on {} after {} retries", script, retries);
+            self.attempts = 0;
+            self.retry_at = 0;
+            return false;
+        }
+        let delay = self.retry_delay.unwrap_or(settings.retry_delay);
+        let delay = self
+            .retry_backoff
+            .unwrap_or(settings.retry_backoff)
+            .checked_pow(self.attempts - 1)
+            .and_then(|x| x.checked_mul(delay))
+            .map_or(MAX_RETRY_DELAY, |x| x.clamp(0, MAX_RETRY_DELAY));
+        self.retry_at = time_now + delay * 60;
+        warn!(
+            "Retrying {} in {} minutes (retry {} of {})",
+            script, delay, self.attempts, retries
+        );
+        true
+    }
+    /// Whether execution exiting with the given code should be considered failed
+    /// Without success_codes and failure_codes every code greater than fail_on_code (or --fail-on-code) is failed
+    pub fn is_failure_code(&self, code: i32) -> bool {
+        if self.success_codes.is_none() && self.failure_codes.is_none() {
+            return code > self.fail_on_code.unwrap_or_else(|| settings::get().fail_on_code);
+        }
+        let success = match &self.success_codes {
+            Some(x) => x.contains(code),
+            None => true,
+        };
+        let failure = match &self.failure_codes {
+            Some(x) => x.contains(code),
+            None => false,
+        };
+        !success || failure
+    }
+    /// Whether execution killed by a signal should be considered successful
+    pub fn signal_success(&self) -> bool {
+        self.signal_success.unwrap_or(settings::get().signal_success)
+    }
+    /// Destinations of the output of the executions
+    /// Attributes of the event take precedence over the settings, stdout_to and stderr_to over pipe and pipe_to of the same level
+    pub fn output(&self) -> Output {
+        let settings = settings::get();
+        self.output_over(
+            (&settings.stdout_to, &settings.stderr_to),
+            settings.pipe,
+            &settings.pipe_to,
+            &settings.file,
+        )
+    }
+    /// Destinations of the output, over the given global stdout_to and stderr_to, pipe, pipe_to and file
+    /// Global stdout_to and stderr_to don't apply when the event sets its pipe or pipe_to
+    fn output_over(
+        &self,
+        (stdout_to, stderr_to): (&Option<Destination>, &Option<Destination>),
+        pipe: Pipe,
+        pipe_to: &PipeTo,
+        file: &Option<PathBuf>,
+    ) -> Output {
+        let pipe_set = self.pipe.is_some() || self.pipe_to.is_some();
+        let destination = |own: &Option<Destination>, global: &Option<Destination>| match own {
+            Some(x) => Some(x.to_owned()),
+            None if pipe_set => None,
+            None => global.to_owned(),
+        };
+        Output::new(
+            destination(&self.stdout_to, stdout_to),
+            destination(&self.stderr_to, stderr_to),
+            self.pipe.unwrap_or(pipe),
+            self.pipe_to.as_ref().unwrap_or(pipe_to),
+            file,
+        )
+    }
+    /// Clears failed attempts after successful execution
+    pub fn reset_retry(&mut self) {
+        self.attempts = 0;
+        self.retry_at = 0;
+    }
+    /// Copies execution state of the same event from the previous plan
+    pub fn keep_state_of(&mut self, previous: &Event) {
+        self.executed = previous.executed;
+        self.skipped = previous.skipped;
+        self.attempts = previous.attempts;
+        self.retry_at = previous.retry_at;
+    }
     pub fn should_reschedule(&self) -> bool {
         self.executed.0 && !self.executed.1
     }
 }
 impl ToString for Event {
     fn to_string(&self) -> String {
-        format!(
+        let mut string = format!(
             "{}{}{}{}",
             self.start, self.end, self.execute_start, self.execute_end
-        )
+        );
+        if let Some(service) = &self.service {
+            string.push_str(&service.run);
+        }
+        // Appended only when present, so checksums of events without during don't change
+        if let Some(during) = &self.during {
+            string.push_str(during);
+        }
+        string
     }
 }
 impl Ord for Event {
@@ -126,6 +389,67 @@ impl PartialEq for Event {
         return self.start == other.start;
     }
 }
+/// Parses identity of the event, integers are accepted too (e.g. id: 5)
+fn parse_id(
+    yaml_object: &Yaml,
+    attribute: &str,
+    weekday: &str,
+) -> Result<Option<String>, BadAttributeValue> {
+    match &yaml_object[attribute] {
+        Yaml::BadValue => Ok(None),
+        Yaml::String(x) => Ok(Some(x.to_owned())),
+        Yaml::Integer(x) => Ok(Some(x.to_string())),
+        _ => Err(BadAttributeValue::new(attribute, weekday)),
+    }
+}
+/// Parses integer attribute, which has to fit the given type (e.g. retries can't be negative)
+fn parse_int<T: TryFrom<i64>>(
+    yaml_object: &Yaml,
+    attribute: &str,
+    weekday: &str,
+) -> Result<Option<T>, BadAttributeValue> {
+    match &yaml_object[attribute] {
+        Yaml::BadValue => Ok(None),
+        x => match x.as_i64().and_then(|x| T::try_from(x).ok()) {
+            Some(x) => Ok(Some(x)),
+            None => Err(BadAttributeValue::new(attribute, weekday)),
+        },
+    }
+}
+/// Parses deprecated fail attribute, holding flags of --fail, superseded by on_fail
+fn parse_fail(yaml_object: &Yaml, weekday: &str) -> Result<Option<OnFail>, BadAttributeValue> {
+    let fail = parse_int::<u32>(yaml_object, "fail", weekday)?.map(OnFail::from_flags);
+    if fail.is_some() {
+        warn!("Attribute fail of events in {} plan is deprecated, use on_fail", weekday);
+    }
+    Ok(fail)
+}
+/// Parses boolean attribute (e.g. signal_success: true)
+fn parse_bool(
+    yaml_object: &Yaml,
+    attribute: &str,
+    weekday: &str,
+) -> Result<Option<bool>, BadAttributeValue> {
+    match &yaml_object[attribute] {
+        Yaml::BadValue => Ok(None),
+        Yaml::Boolean(x) => Ok(Some(*x)),
+        _ => Err(BadAttributeValue::new(attribute, weekday)),
+    }
+}
+/// Parses attribute holding names (e.g. pipe: [stdout, stderr]), like the command line option of the same name
+fn parse_names<T: FromStr>(
+    yaml_object: &Yaml,
+    attribute: &str,
+    weekday: &str,
+) -> Result<Option<T>, BadAttributeValue> {
+    match &yaml_object[attribute] {
+        Yaml::BadValue => Ok(None),
+        x => match yaml_names(x).and_then(|x| x.parse::<T>().ok()) {
+            Some(x) => Ok(Some(x)),
+            None => Err(BadAttributeValue::new(attribute, weekday)),
+        },
+    }
+}
 pub fn parse_time(time: &str, weekday: &str) -> Result<i64, Box<dyn std::error::Error>> {
     let mut split = time.split(':');
     let now = chrono::Local::now();
@@ -152,3 +476,96 @@ pub enum ExecutionType {
     LOOP,
     NONE,
 }
+impl ExecutionType {
+    /// Name of the execution phase, as passed to hooks
+    pub fn phase(&self) -> &'static str {
+        match self {
+            ExecutionType::START => "start",
+            ExecutionType::END => "end",
+            ExecutionType::LOOP => "during",
+            ExecutionType::NONE => "none",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yaml_rust::YamlLoader;
+
+    fn event(attributes: &str) -> Result<Event, Box<dyn std::error::Error>> {
+        let yaml = YamlLoader::load_from_str(&format!(
+            "{{start: \"10:00\", end: \"11:00\", execute_start: /bin/true, execute_end: /bin/true, {}}}",
+            attributes
+        ))
+        .unwrap()
+        .remove(0);
+        Event::new(&yaml, &Cow::Borrowed("monday"))
+    }
+
+    #[test]
+    fn reads_distances_and_signal_success() {
+        let event =
+            event("distance: 5, distance_start: 0, distance_end: 10, signal_success: true").unwrap();
+        assert_eq!(event.distance, Some(5));
+        assert_eq!(event.distance_start, Some(0));
+        assert_eq!(event.distance_end, Some(10));
+        assert_eq!(event.signal_success, Some(true));
+    }
+    #[test]
+    fn reads_deprecated_fail_as_on_fail() {
+        assert_eq!(event("fail: 1").unwrap().on_fail, Some(OnFail { retry: true }));
+        assert_eq!(event("fail: 0").unwrap().on_fail, Some(OnFail { retry: false }));
+        assert_eq!(event("fail: 1, on_fail: none").unwrap().on_fail, Some(OnFail { retry: false }));
+        assert!(event("fail: retry").is_err());
+    }
+    #[test]
+    fn destinations_of_event_take_precedence_over_global_ones() {
+        let file = Some(PathBuf::from("/var/log/ontime.log"));
+        let global = |x: &str| Some(x.parse::<Destination>().unwrap());
+        let output = |attributes: &str, stdout_to: &str, stderr_to: &str| {
+            let output = event(attributes).unwrap().output_over(
+                (&global(stdout_to), &global(stderr_to)),
+                Pipe::STDOUT,
+                &PipeTo::STDERR,
+                &file,
+            );
+            (output.stdout, output.stderr)
+        };
+        let to = |x: &str| x.parse::<Destination>().unwrap();
+        // Attributes of the event, global stdout_to and stderr_to, resulting stdout and stderr
+        let cases = [
+            ("stdout_to: none, stderr_to: ./err.log", "syslog", "syslog", ("none", "./err.log")),
+            ("stderr_to: ./err.log", "syslog", "syslog", ("syslog", "./err.log")),
+            ("other: 1", "./out.log", "none", ("./out.log", "none")),
+            // Event piping its output doesn't go to the global destinations
+            ("pipe: stderr", "./out.log", "./err.log", ("stdout", "stderr")),
+            ("pipe_to: file", "./out.log", "./err.log", ("/var/log/ontime.log", "stderr")),
+            ("pipe_to: file, stderr_to: combined", "none", "none", ("/var/log/ontime.log", "/var/log/ontime.log")),
+        ];
+        for (attributes, stdout_to, stderr_to, (stdout, stderr)) in &cases {
+            assert_eq!(
+                output(attributes, stdout_to, stderr_to),
+                (to(stdout), to(stderr)),
+                "{} over {} and {}",
+                attributes,
+                stdout_to,
+                stderr_to
+            );
+        }
+    }
+    #[test]
+    fn rejects_malformed_distances_and_signal_success() {
+        for attributes in &[
+            "distance: \"5\"",
+            "distance: 5.0",
+            "distance: [5]",
+            "distance_start: -1",
+            "distance_end: 4294967296",
+            "signal_success: \"yes\"",
+            "signal_success: 1",
+        ] {
+            assert!(event(attributes).is_err(), "{} was accepted", attributes);
+        }
+    }
+}
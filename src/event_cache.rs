@@ -1,113 +1,413 @@
-use crate::directory::find_env_dir_or_etc;
+use crate::directory::xdg_dir;
 use crate::event::Event;
 use crate::event_list::List;
-use chrono::NaiveDate;
+use crate::logger::Decision;
+use crate::{APP_NAME, CONFIG};
+use chrono::{offset::Local, DateTime, NaiveDate};
+use log::Level;
+use serde::{Deserialize, Serialize};
+use std::env;
 use std::fs;
 use std::io::{self, BufRead, Seek, Write};
+use std::path::{Path, PathBuf};
 const CACHE_FILE_NAME: &'static str = "ontime.cache";
 
+const SYSTEM_STATE_DIR: &str = "/var/lib/ontime";
+// Cache of older versions, used when $XDG_CACHE_HOME wasn't set
+const LEGACY_SYSTEM_CACHE: &str = "/etc/ontime.cache";
+
+/// Returns path of a file storing ontime's state (e.g. the history), creating its directory if it's missing
+/// Files are kept in --state-dir, /var/lib/ontime in system mode or $XDG_STATE_HOME/ontime (~/.local/state/ontime)
+pub fn state_file_path(file_name: &str) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let mut path = match &CONFIG.state_dir {
+        Some(x) => x.to_owned(),
+        None => default_dir("XDG_STATE_HOME", ".local/state"),
+    };
+    fs::create_dir_all(&path)?;
+    path.push(file_name);
+    Ok(path)
+}
+/// Returns path of the cache, creating its directory if it's missing
+/// Cache is kept at --cache, in /var/lib/ontime in system mode or $XDG_CACHE_HOME/ontime (~/.cache/ontime)
+pub fn cache_file_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let path = match &CONFIG.cache {
+        Some(x) => x.to_owned(),
+        None => {
+            let mut path = default_dir("XDG_CACHE_HOME", ".cache");
+            path.push(CACHE_FILE_NAME);
+            path
+        }
+    };
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)?;
+    }
+    if CONFIG.cache.is_none() && !path.exists() {
+        migrate_legacy_cache(&path)?;
+    }
+    Ok(path)
+}
+/// Moves the cache from where older versions kept it ($XDG_CACHE_HOME/ontime.cache or /etc/ontime.cache),
+/// so today's events already executed aren't executed again after the upgrade
+fn migrate_legacy_cache(path: &Path) -> io::Result<()> {
+    let legacy = env::var_os("XDG_CACHE_HOME")
+        .map(|x| PathBuf::from(x).join(CACHE_FILE_NAME))
+        .into_iter()
+        .chain(Some(PathBuf::from(LEGACY_SYSTEM_CACHE)))
+        .find(|x| x.is_file());
+    let legacy = match legacy {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+    info!("Moving cache from {:?} to {:?}", legacy, path);
+    // Rename fails across filesystems, or when the directory of the legacy cache isn't writable
+    if fs::rename(&legacy, path).is_err() {
+        fs::copy(&legacy, path)?;
+        if let Err(err) = fs::remove_file(&legacy) {
+            warn!("Couldn't remove legacy cache {:?}: {}", legacy, err);
+        }
+    }
+    Ok(())
+}
+/// Returns /var/lib/ontime in system mode or when neither the XDG variable nor $HOME is set
+fn default_dir(env_var: &str, home_dir: &str) -> PathBuf {
+    if CONFIG.system {
+        return PathBuf::from(SYSTEM_STATE_DIR);
+    }
+    xdg_dir(env_var, home_dir, APP_NAME).unwrap_or_else(|| PathBuf::from(SYSTEM_STATE_DIR))
+}
+
+/// Version of the cache format written by this version of ontime
+/// Version 1 had a plain date header and "checksum executed_start executed_end [attempts retry_at]" lines
+const CACHE_VERSION: u32 = 2;
+
+/// First line of the cache
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    version: u32,
+    date: String,
+}
+/// Execution status of a single event
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    event: String,
+    executed_start: bool,
+    executed_end: bool,
+    #[serde(default)]
+    attempts: u32,
+    #[serde(default)]
+    retry_at: i64,
+}
+
 /// Struct holding variables responsible for reading through cache
-/// Cache contains execution status of today's events
-/// Header (first line) should contain date
-/// Then every event line is going to be in form of checksum and two 0 or 1 (first for start, second for end script) meaning whether execution happend
+/// Cache contains execution status of today's events, as json lines
+/// Header (first line) contains version of the format and date
+/// Then every event line contains identity of the event (its id, or checksum if it has none), whether start and end script were executed,
+/// the number of failed attempts of the pending execution and the timestamp of its next retry
+/// Caches in older formats are read and rewritten in the current one, entries that can't be parsed are skipped
 
 #[derive(Debug)]
 pub struct Cache {
-    date: NaiveDate,
-    // TODO: Replace with actual descriptor type??
+    // Modification date of the file when the header couldn't be read, None if that's unknown too, see cleanup
+    date: Option<NaiveDate>,
+    // Version of the format the file was read in, 0 when the header couldn't be read
+    version: u32,
+    path: PathBuf,
+    // Replaced with the new file every time the cache is written
     descriptor: fs::File,
 }
 impl Cache {
-    /// Tries to read cache, firstly in $XDG_CONFIG_HOME/ontime directory, then in /etc/ontime
-    /// A File where the cache is storied is named ontime.cache
-    /// If file doesn't have a header (file is empty) it sets date to 0000-00-00 so cleanup should be always called, right after initialization
+    /// Reads the cache, by default $XDG_CACHE_HOME/ontime/ontime.cache
+    /// If file is empty it sets date to 1990-01-01 so cleanup should be always called, right after initialization
     pub fn initial_read_cache() -> Result<Self, Box<dyn std::error::Error>> {
-        let mut path = find_env_dir_or_etc("XDG_CACHE_HOME", "")?;
-        path.push(CACHE_FILE_NAME);
+        Ok(Self::read(cache_file_path()?)?)
+    }
+    /// Reads the cache at the given path
+    /// Cache without a readable header is dated by the last modification of the file, as it was last written then
+    fn read(path: PathBuf) -> io::Result<Self> {
         let descriptor = fs::OpenOptions::new()
             .read(true)
             .write(true)
             .append(false)
             .create(true)
-            .open(path)?;
+            .open(&path)?;
         let reader = io::BufReader::new(&descriptor);
         let mut lines = reader.lines();
-        let date = match lines.next() {
-            None => NaiveDate::from_ymd(1990, 1, 1),
-            Some(x) => NaiveDate::parse_from_str(&x?, "%Y-%m-%d")?,
+        let (date, version) = match lines.next() {
+            None => (Some(NaiveDate::from_ymd(1990, 1, 1)), CACHE_VERSION),
+            Some(x) => match read_header(&x?) {
+                (None, version) => (modified_date(&descriptor), version),
+                x => x,
+            },
         };
-        Ok(Self { date, descriptor })
+        Ok(Self {
+            date,
+            version,
+            path,
+            descriptor,
+        })
     }
     /// Checks whether the date of the cache matches the current date
     /// If not, empties the file and writes the current date.
+    /// Cache of unknown date is kept as today's, as emptying it would execute today's events again
     pub fn cleanup(&mut self, time_now: &NaiveDate) -> io::Result<()> {
-        if &self.date == time_now {
-            return Ok(());
+        match self.date {
+            Some(x) if &x == time_now => return Ok(()),
+            None => {
+                self.date = Some(time_now.to_owned());
+                return Ok(());
+            }
+            _ => (),
         }
         debug!("Cleaning cache");
-        self.date = time_now.to_owned();
-        self.descriptor.set_len(0)?;
-        self.descriptor.seek(io::SeekFrom::Start(0))?;
-        self.descriptor.write_all(self.date.to_string().as_bytes())
+        self.date = Some(time_now.to_owned());
+        self.version = CACHE_VERSION;
+        let header = self.header()?;
+        self.replace(&header)
     }
     pub fn full_read_cache(&mut self, event_list: &mut List<Event>) -> io::Result<()> {
         self.descriptor.seek(io::SeekFrom::Start(0))?;
         let reader = io::BufReader::new(&self.descriptor);
         let mut lines = reader.lines();
-        // Skip first line as it contains date only
-        match lines.next() {
-            None => return Ok(()),
-            _ => {}
+        // Skip first line as it contains the header only
+        if lines.next().is_none() {
+            return Ok(());
         }
-        while let Some(x) = lines.next() {
-            let x = x.unwrap();
-            // MD5 checksum (first 32 characters of the line)
-            if x.len() == 0 {
-                return Ok(())
-            }
-            let mut split = x.split(" ");
-            let checksum = split.next().unwrap();
-            let executed_start: bool = match split.next().unwrap().parse::<bool>() {
-                Err(_) => false,
+        for (number, line) in lines.enumerate() {
+            let line = match line {
                 Ok(x) => x,
+                Err(err) => {
+                    warn!("Couldn't read cache line {}: {}", number + 2, err);
+                    continue;
+                }
             };
-            let executed_end: bool = match split.next().unwrap().parse::<bool>() {
-                Err(_) => false,
-                Ok(x) => x,
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry = match parse_entry(self.version, &line) {
+                Some(x) => x,
+                None => {
+                    warn!("Skipping malformed cache entry on line {}: {}", number + 2, line);
+                    continue;
+                }
             };
             let mut current_link = event_list.head.as_mut();
             while let Some(item) = current_link {
-                let mut equal = false;
-                if let Some(x) = &item.value.checksum {
-                    if x.as_str() == checksum {
-                        equal = true;
-                    }
-                }
-                if equal {
-                    item.value.executed = (executed_start, executed_end);
+                if item.value.key() == entry.event {
+                    item.value.executed = (entry.executed_start, entry.executed_end);
+                    item.value.attempts = entry.attempts;
+                    item.value.retry_at = entry.retry_at;
                 }
                 current_link = item.next_mut();
             }
         }
+        if self.version < CACHE_VERSION {
+            self.write(event_list)?;
+        }
         Ok(())
     }
     // TODO: Rewrite to print affected event instead of whole file
     pub fn write(&mut self, event_list: &List<Event>) -> io::Result<()> {
-        let mut to_write: String = String::new();
-        to_write.push_str(&format!("{}\n", self.date.to_string()));
+        self.version = CACHE_VERSION;
+        let mut to_write: String = self.header()?;
         let mut current_link = event_list.head.as_ref();
-        self.descriptor.set_len(0)?;
-        self.descriptor.seek(io::SeekFrom::Start(0))?;
         while let Some(item) = current_link {
-            if let Some(x) = &item.value.checksum {
-                to_write.push_str(&format!(
-                    "{} {} {}\n",
-                    x, &item.value.executed.0, &item.value.executed.1
-                ));
-            }
+            let entry = Entry {
+                event: item.value.key(),
+                executed_start: item.value.executed.0,
+                executed_end: item.value.executed.1,
+                attempts: item.value.attempts,
+                retry_at: item.value.retry_at,
+            };
+            to_write.push_str(&serde_json::to_string(&entry)?);
+            to_write.push('\n');
             current_link = item.next_ref();
         }
-        let mut writer = io::LineWriter::new(&self.descriptor);
-        writer.write_all(to_write.as_bytes())
+        self.replace(&to_write)?;
+        Decision::new("cache_written")
+            .field("date", self.date.map(|x| x.to_string()))
+            .log(Level::Info, "Cache written");
+        Ok(())
+    }
+    /// Atomically replaces content of the cache
+    /// Content is written to a temporary file in the same directory, synced to disk and renamed over the cache,
+    /// so a crash never leaves the cache empty or partially written
+    fn replace(&mut self, content: &str) -> io::Result<()> {
+        let mut temporary_path = self.path.to_owned().into_os_string();
+        temporary_path.push(".tmp");
+        let mut temporary = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&temporary_path)?;
+        temporary.write_all(content.as_bytes())?;
+        temporary.sync_all()?;
+        fs::rename(&temporary_path, &self.path)?;
+        // Sync the directory too, so the rename itself survives a power loss
+        if let Some(directory) = self.path.parent() {
+            fs::File::open(directory)?.sync_all()?;
+        }
+        self.descriptor = temporary;
+        Ok(())
+    }
+    fn header(&self) -> io::Result<String> {
+        let header = Header {
+            version: CACHE_VERSION,
+            date: self.date.map(|x| x.to_string()).unwrap_or_default(),
+        };
+        Ok(format!("{}\n", serde_json::to_string(&header)?))
+    }
+}
+/// Reads date and format version from the first line of the cache
+/// Returns no date and version 0 if the header is malformed or the version isn't supported
+fn read_header(line: &str) -> (Option<NaiveDate>, u32) {
+    // Version 1 header contains only the date
+    if let Ok(date) = NaiveDate::parse_from_str(line.trim(), "%Y-%m-%d") {
+        debug!("Migrating cache from version 1");
+        return (Some(date), 1);
+    }
+    let header = match serde_json::from_str::<Header>(line) {
+        Ok(x) => x,
+        Err(err) => {
+            error!("Malformed cache header ({}), dating the cache by its modification", err);
+            return (None, 0);
+        }
+    };
+    if header.version > CACHE_VERSION {
+        error!(
+            "Cache version {} isn't supported, dating the cache by its modification",
+            header.version
+        );
+        return (None, 0);
+    }
+    match NaiveDate::parse_from_str(&header.date, "%Y-%m-%d") {
+        Ok(date) => (Some(date), header.version),
+        Err(err) => {
+            error!("Malformed cache date ({}), dating the cache by its modification", err);
+            (None, 0)
+        }
+    }
+}
+/// Returns local date of the last modification of the file
+fn modified_date(file: &fs::File) -> Option<NaiveDate> {
+    let modified = file.metadata().and_then(|x| x.modified()).ok()?;
+    Some(DateTime::<Local>::from(modified).date().naive_local())
+}
+/// Parses entry of the cache in the given version, entry of a cache with unknown version can be in any of them
+fn parse_entry(version: u32, line: &str) -> Option<Entry> {
+    match version {
+        1 => parse_v1_entry(line),
+        CACHE_VERSION => serde_json::from_str::<Entry>(line).ok(),
+        _ => serde_json::from_str::<Entry>(line)
+            .ok()
+            .or_else(|| parse_v1_entry(line)),
+    }
+}
+/// Parses "checksum executed_start executed_end [attempts retry_at]" line of version 1 cache
+fn parse_v1_entry(line: &str) -> Option<Entry> {
+    let mut split = line.split(' ');
+    let event = split.next()?.to_owned();
+    let executed_start = split.next()?.parse::<bool>().ok()?;
+    let executed_end = split.next()?.parse::<bool>().ok()?;
+    // Attempts and retry time are missing in caches written by older versions
+    let attempts = split.next().and_then(|x| x.parse().ok()).unwrap_or(0);
+    let retry_at = split.next().and_then(|x| x.parse().ok()).unwrap_or(0);
+    Some(Entry {
+        event,
+        executed_start,
+        executed_end,
+        attempts,
+        retry_at,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(version: u32, lines: &[&str]) -> Vec<(String, bool, bool, u32, i64)> {
+        lines
+            .iter()
+            .filter_map(|x| parse_entry(version, x))
+            .map(|x| (x.event, x.executed_start, x.executed_end, x.attempts, x.retry_at))
+            .collect()
+    }
+
+    #[test]
+    fn reads_version_1_cache() {
+        assert_eq!(read_header("2026-10-19"), (Some(NaiveDate::from_ymd(2026, 10, 19)), 1));
+        assert_eq!(
+            entries(1, &["abc true false", "def true true 2 1792380568", "broken"]),
+            vec![
+                ("abc".to_owned(), true, false, 0, 0),
+                ("def".to_owned(), true, true, 2, 1792380568),
+            ]
+        );
+    }
+    #[test]
+    fn reads_version_2_cache() {
+        assert_eq!(
+            read_header(r#"{"version":2,"date":"2026-10-19"}"#),
+            (Some(NaiveDate::from_ymd(2026, 10, 19)), 2)
+        );
+        assert_eq!(
+            entries(
+                2,
+                &[
+                    r#"{"event":"backup","executed_start":true,"executed_end":false}"#,
+                    r#"{"event":"sync","executed_start":true,"executed_end":true,"attempts":1,"retry_at":60}"#,
+                    "abc true false",
+                ]
+            ),
+            vec![
+                ("backup".to_owned(), true, false, 0, 0),
+                ("sync".to_owned(), true, true, 1, 60),
+            ]
+        );
+    }
+    // Writes the cache with the given content and modification time, then reads and cleans it up as on a start
+    fn cleaned_up(name: &str, content: &str, modified: std::time::SystemTime) -> (NaiveDate, String) {
+        let path = std::env::temp_dir().join(format!("ontime-cache-{}-{}", std::process::id(), name));
+        fs::write(&path, content).unwrap();
+        fs::File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+        let mut cache = Cache::read(path.to_owned()).unwrap();
+        cache.cleanup(&Local::now().date().naive_local()).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        (cache.date.unwrap(), content)
+    }
+
+    #[test]
+    fn keeps_entries_of_cache_with_corrupt_header_written_today() {
+        let content = "{\"version\":\nabc true false\n";
+        let (date, kept) = cleaned_up("today", content, std::time::SystemTime::now());
+        assert_eq!(date, Local::now().date().naive_local());
+        assert_eq!(kept, content);
+    }
+    #[test]
+    fn empties_cache_with_corrupt_header_written_before_today() {
+        let yesterday = std::time::SystemTime::now() - std::time::Duration::from_secs(24 * 60 * 60);
+        let (date, emptied) = cleaned_up("yesterday", "{\"version\":\nabc true false\n", yesterday);
+        assert_eq!(date, Local::now().date().naive_local());
+        assert_eq!(emptied, format!("{{\"version\":2,\"date\":\"{}\"}}\n", date));
+    }
+    #[test]
+    fn reads_entries_of_cache_with_corrupt_header() {
+        assert_eq!(read_header("{\"version\":"), (None, 0));
+        assert_eq!(read_header(r#"{"version":3,"date":"2026-10-19"}"#), (None, 0));
+        assert_eq!(read_header(r#"{"version":2,"date":"yesterday"}"#), (None, 0));
+        assert_eq!(
+            entries(
+                0,
+                &[
+                    r#"{"event":"backup","executed_start":true,"executed_end":false}"#,
+                    "abc true true",
+                ]
+            ),
+            vec![
+                ("backup".to_owned(), true, false, 0, 0),
+                ("abc".to_owned(), true, true, 0, 0),
+            ]
+        );
     }
 }
@@ -0,0 +1,198 @@
+use crate::errors::BadAttributeValue;
+use chrono::offset::Local;
+use chrono::NaiveDate;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use yaml_rust::Yaml;
+
+/// Log file capturing output of an event's executions
+/// Every line is prefixed with a timestamp and the stream (stdout or stderr) it comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventLog {
+    // Path of the file, {date}, {phase} and {event} are replaced when it's opened
+    path: String,
+    // Rotate the file once it's bigger than the given size (in bytes)
+    max_size: Option<u64>,
+    // Rotate the file once it's older than the given age (in days)
+    max_age: Option<u64>,
+    // Number of rotated files kept, older are removed
+    // Also number of files of previous dates kept, when the file name contains {date}
+    keep: u32,
+}
+impl EventLog {
+    /// Reads log, log_max_size, log_max_age and log_keep attributes of an event
+    /// Returns None if the event doesn't have log field
+    pub fn new(yaml_object: &Yaml, weekday: &str) -> Result<Option<Self>, BadAttributeValue> {
+        let path = match yaml_object["log"].as_str() {
+            Some(x) => x.to_owned(),
+            None => return Ok(None),
+        };
+        let positive = |attribute: &str| -> Result<Option<u64>, BadAttributeValue> {
+            match &yaml_object[attribute] {
+                Yaml::BadValue => Ok(None),
+                Yaml::Integer(x) if *x >= 0 => Ok(Some(*x as u64)),
+                _ => Err(BadAttributeValue::new(attribute, weekday)),
+            }
+        };
+        Ok(Some(Self {
+            path,
+            max_size: positive("log_max_size")?,
+            max_age: positive("log_max_age")?,
+            keep: positive("log_keep")?.unwrap_or(5) as u32,
+        }))
+    }
+    /// Opens the log file for an execution, rotating it first if it's too big or too old
+    /// Event name is sanitized, so it can't point outside of the log's directory
+    pub fn open(&self, event: &str, phase: &str) -> io::Result<LogWriter> {
+        let template = self
+            .path
+            .replace("{phase}", phase)
+            .replace("{event}", &event.replace('/', "_").replace("..", "_"));
+        let today = Local::now().format("%Y-%m-%d").to_string();
+        let path = PathBuf::from(template.replace("{date}", &today));
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Logging shouldn't fail, just because old logs couldn't be removed
+        if let Err(err) = self.prune_dated(Path::new(&template), &today) {
+            warn!("Couldn't remove old logs of {:?}: {}", path, err);
+        }
+        if self.should_rotate(&path) {
+            self.rotate(&path)?;
+        }
+        let file = OpenOptions::new().append(true).create(true).open(&path)?;
+        Ok(LogWriter {
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+    fn should_rotate(&self, path: &Path) -> bool {
+        let metadata = match fs::metadata(path) {
+            Ok(x) => x,
+            Err(_) => return false,
+        };
+        if let Some(max_size) = self.max_size {
+            if metadata.len() > max_size {
+                return true;
+            }
+        }
+        if let Some(max_age) = self.max_age {
+            // Creation time isn't supported by every filesystem
+            let created = metadata.created().or_else(|_| metadata.modified());
+            let age = created
+                .ok()
+                .and_then(|x| SystemTime::now().duration_since(x).ok())
+                .unwrap_or_default();
+            if age > Duration::from_secs(max_age * 24 * 60 * 60) {
+                return true;
+            }
+        }
+        false
+    }
+    /// Renames the file to <path>.1, shifting already rotated files by one
+    /// File rotated more than keep times is removed
+    fn rotate(&self, path: &Path) -> io::Result<()> {
+        let rotated = |n: u32| PathBuf::from(format!("{}.{}", path.display(), n));
+        if self.keep == 0 {
+            return fs::remove_file(path);
+        }
+        if rotated(self.keep).exists() {
+            fs::remove_file(rotated(self.keep))?;
+        }
+        for n in (1..self.keep).rev() {
+            if rotated(n).exists() {
+                fs::rename(rotated(n), rotated(n + 1))?;
+            }
+        }
+        debug!("Rotating log {:?}", path);
+        fs::rename(path, rotated(1))
+    }
+    /// Removes files of previous dates (with their rotations), except the keep newest ones
+    /// Only dates in the file name are pruned, not in the names of directories
+    fn prune_dated(&self, template: &Path, today: &str) -> io::Result<()> {
+        let name = match template.file_name().and_then(|x| x.to_str()) {
+            Some(x) if x.contains("{date}") => x,
+            _ => return Ok(()),
+        };
+        let mut parts = name.splitn(2, "{date}");
+        let (prefix, suffix) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+        let dir = match template.parent() {
+            Some(x) if !x.as_os_str().is_empty() => x,
+            _ => Path::new("."),
+        };
+        let mut dated = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let date = path
+                .file_name()
+                .and_then(|x| x.to_str())
+                .and_then(|x| log_date(x, prefix, suffix));
+            match date {
+                Some(x) if x != today && path.is_file() => dated.push((x, path)),
+                _ => (),
+            }
+        }
+        let mut dates = dated.iter().map(|(x, _)| x.to_owned()).collect::<Vec<String>>();
+        dates.sort();
+        dates.dedup();
+        let removed = &dates[..dates.len().saturating_sub(self.keep as usize)];
+        for (date, path) in &dated {
+            if removed.contains(date) {
+                debug!("Removing old log {:?}", path);
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+/// Returns date of the log file, if its name is the template's prefix, a date, the suffix and optionally a rotation (.1)
+fn log_date(file_name: &str, prefix: &str, suffix: &str) -> Option<String> {
+    let rest = file_name.strip_prefix(prefix)?;
+    let date = rest.get(..10)?;
+    NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+    let rotation = rest[10..].strip_prefix(suffix)?;
+    match rotation.strip_prefix('.') {
+        Some(x) if !x.is_empty() && x.chars().all(|x| x.is_ascii_digit()) => Some(date.to_owned()),
+        None if rotation.is_empty() => Some(date.to_owned()),
+        _ => None,
+    }
+}
+
+/// Opened log file, shared between threads reading stdout and stderr of the execution
+#[derive(Debug, Clone)]
+pub struct LogWriter {
+    file: Arc<Mutex<fs::File>>,
+}
+impl LogWriter {
+    /// Copies every line from the stream to the log, tagged with the stream name
+    /// Returns everything that was read
+    pub fn copy_lines<R: Read>(&self, stream: R, name: &str) -> io::Result<Vec<u8>> {
+        let mut content = Vec::new();
+        for line in BufReader::new(stream).split(b'\n') {
+            let line = line?;
+            self.write_line(&line, name)?;
+            content.extend_from_slice(&line);
+            content.push(b'\n');
+        }
+        Ok(content)
+    }
+    /// Copies every line from the stream to the log, without keeping them, e.g. for services running all day
+    pub fn append_lines<R: Read>(&self, stream: R, name: &str) -> io::Result<()> {
+        for line in BufReader::new(stream).split(b'\n') {
+            self.write_line(&line?, name)?;
+        }
+        Ok(())
+    }
+    fn write_line(&self, line: &[u8], name: &str) -> io::Result<()> {
+        let mut file = self.file.lock().unwrap();
+        writeln!(
+            file,
+            "{} [{}] {}",
+            Local::now().format("%Y-%m-%d %H:%M:%S"),
+            name,
+            String::from_utf8_lossy(line)
+        )
+    }
+}
@@ -1,17 +1,21 @@
-use crate::config;
 use crate::errors::ExecutionError;
 use crate::event::{Event, ExecutionType};
-use crate::CONFIG;
-use std::fs::OpenOptions;
-use std::io::stderr;
-use std::os::unix::io::AsRawFd;
+use crate::event_log::LogWriter;
+use crate::history;
+use crate::logger::Decision;
+use crate::output::{self, Output};
+use crate::service;
+use crate::signals;
+use chrono::{offset::Local, DateTime};
+use log::Level;
+use nix::sys::signal::Signal;
+use std::convert::TryFrom;
+use std::io::Read;
+use std::thread;
+use std::time::{Duration, Instant};
 // According to docs Child::output.status() requires this trait to import to return signals as well
-#[allow(unused_imports)]
 use std::os::unix::process::ExitStatusExt;
-use std::{
-    os::unix::prelude::FromRawFd,
-    process::{Command, Stdio},
-};
+use std::process::{Child, Command, ExitStatus, Stdio};
 pub fn process_event(
     event: &mut Event,
     execution_type: &ExecutionType,
@@ -19,68 +23,291 @@ pub fn process_event(
     match execution_type {
         ExecutionType::START => {
             event.executed = (true, event.executed.1);
-            let result = execute(event.execute_start.as_str());
-            if let Err(x) = &result {
-                if x.is::<ExecutionError>() && (CONFIG.fail & config::FAIL_RETRY) > 0
-                {
-                    debug!("OK");
-                    event.executed = (false, event.executed.1);
+            if let Some(service) = &event.service {
+                return service::start(event, service);
+            }
+            let result = execute(event.execute_start.as_str(), event, execution_type);
+            event.hooks.run(&event.key(), execution_type, &result);
+            match &result {
+                Err(x) if x.is::<ExecutionError>() => {
+                    let script = event.execute_start.to_owned();
+                    if event.schedule_retry(&Local::now().timestamp(), &script) {
+                        event.executed = (false, event.executed.1);
+                    }
                 }
+                _ => event.reset_retry(),
             }
-            return result;
+            return result.map(|_| ());
         }
         ExecutionType::END => {
             event.executed = (event.executed.0, true);
-            let result = execute(event.execute_end.as_str());
-            if let Err(x) = &result {
-                if x.is::<ExecutionError>() && (CONFIG.fail & config::FAIL_RETRY) > 0 {
-                    debug!("OK");
-                    event.executed = (event.executed.0, false);
+            if let Some(service) = &event.service {
+                return service::stop(event, service);
+            }
+            let result = execute(event.execute_end.as_str(), event, execution_type);
+            event.hooks.run(&event.key(), execution_type, &result);
+            match &result {
+                Err(x) if x.is::<ExecutionError>() => {
+                    let script = event.execute_end.to_owned();
+                    if event.schedule_retry(&Local::now().timestamp(), &script) {
+                        event.executed = (event.executed.0, false);
+                    }
                 }
+                _ => event.reset_retry(),
             }
-            return result;
+            return result.map(|_| ());
         }
         ExecutionType::LOOP => {
+            if let Some(service) = &event.service {
+                return service::supervise(event, service);
+            }
             if let Some(during) = &event.during {
-                return execute(during.as_str());
+                let result = execute(during.as_str(), event, execution_type);
+                event.hooks.run(&event.key(), execution_type, &result);
+                return result.map(|_| ());
             }
             return Ok(());
         }
         _ => return Ok(()),
     };
 }
-pub fn execute(what: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let mut split = what.split(" ");
-    let mut builder = Command::new(split.next().unwrap());
-    builder.args(split);
-    if CONFIG.pipe > config::PIPE_FROM_NONE {
-        if (CONFIG.pipe & config::PIPE_FROM_STDERR) > 0 {
-            builder.stderr(pipe_to()?);
+/// Output of a successful execution
+#[derive(Debug, Clone)]
+pub struct ExecutionOutput {
+    // Exit code, or 128 + signal number if killed by a signal
+    pub code: i32,
+    // Name of the signal that killed the script
+    pub signal: Option<String>,
+    // Captured standard error, empty if it wasn't captured
+    pub stderr: String,
+}
+/// Spawned command of an event
+#[derive(Debug)]
+pub struct Spawned {
+    pub child: Child,
+    // Time the command was spawned at
+    started: DateTime<Local>,
+    stopwatch: Instant,
+}
+impl Spawned {
+    /// Time since the command was spawned
+    pub fn elapsed(&self) -> Duration {
+        self.stopwatch.elapsed()
+    }
+}
+/// Executes the given string for the event and waits for it to finish
+/// Output goes to the event's log if it has one, else to the destinations of the event or the settings
+/// Standard error is captured when the event has hooks, it's still sent to its destination afterwards
+pub fn execute(
+    what: &str,
+    event: &Event,
+    execution_type: &ExecutionType,
+) -> Result<ExecutionOutput, Box<dyn std::error::Error>> {
+    let capture_stderr = event.hooks.is_set();
+    let log = open_log(event, execution_type)?;
+    let output = event.output();
+    let mut builder = build_command_to(what, &output)?;
+    if log.is_some() {
+        builder.stdout(Stdio::piped());
+    }
+    if capture_stderr || log.is_some() {
+        builder.stderr(Stdio::piped());
+    }
+    let mut spawned = spawn(what, event, execution_type, &mut builder)?;
+    let child = &mut spawned.child;
+    let _watchdog = signals::kill_on_shutdown(child.id());
+    // Stdout is copied on a separate thread, so the script doesn't block on either of the pipes
+    // Without the event's log it's piped only when it goes to syslog
+    let stdout_copy = match (&log, child.stdout.take()) {
+        (Some(log), Some(stdout)) => {
+            let log = log.to_owned();
+            Some(thread::spawn(move || log.copy_lines(stdout, "stdout").map(|_| ())))
+        }
+        (None, Some(stdout)) => {
+            let key = event.key();
+            Some(thread::spawn(move || output::copy_to_syslog(stdout, &key, "stdout")))
         }
-        if (CONFIG.pipe & config::PIPE_FROM_STDOUT) > 0 {
-            builder.stdout(pipe_to()?);
+        _ => None,
+    };
+    let mut stderr = Vec::new();
+    if let Some(mut child_stderr) = child.stderr.take() {
+        match &log {
+            Some(log) => stderr = log.copy_lines(child_stderr, "stderr")?,
+            None if capture_stderr => {
+                child_stderr.read_to_end(&mut stderr)?;
+            }
+            None => output::copy_to_syslog(child_stderr, &event.key(), "stderr")?,
         }
     }
-    let output = builder.spawn()?.wait_with_output()?;
-    let code = output.status.code().unwrap();
-    if code > CONFIG.fail_on_code {
-        error!("Execution failed with code {}", code);
-        // TODO: Implement behaviour
-        let err = ExecutionError {};
+    if let Some(copy) = stdout_copy {
+        copy.join().unwrap()?;
+    }
+    let status = child.wait()?;
+    if capture_stderr && log.is_none() {
+        output::forward(&stderr, &output.stderr, &event.key(), "stderr")?;
+    }
+    let stderr = String::from_utf8_lossy(&stderr).into_owned();
+    exited(what, event, execution_type, &spawned, status, stderr, None)
+}
+/// Spawns the long-running command of the event (e.g. a service), without waiting for it
+/// Output goes where it goes for execute, it's copied on separate threads until the command exits
+pub fn spawn_detached(
+    what: &str,
+    event: &Event,
+    execution_type: &ExecutionType,
+) -> Result<Spawned, Box<dyn std::error::Error>> {
+    let log = open_log(event, execution_type)?;
+    let mut builder = build_command_to(what, &event.output())?;
+    if log.is_some() {
+        builder.stdout(Stdio::piped()).stderr(Stdio::piped());
+    }
+    let mut spawned = spawn(what, event, execution_type, &mut builder)?;
+    let child = &mut spawned.child;
+    match log {
+        Some(log) => {
+            if let Some(stdout) = child.stdout.take() {
+                let log = log.to_owned();
+                thread::spawn(move || log.append_lines(stdout, "stdout"));
+            }
+            if let Some(stderr) = child.stderr.take() {
+                thread::spawn(move || log.append_lines(stderr, "stderr"));
+            }
+        }
+        None => output::copy_to_syslog_detached(child, &event.key()),
+    }
+    Ok(spawned)
+}
+fn open_log(
+    event: &Event,
+    execution_type: &ExecutionType,
+) -> Result<Option<LogWriter>, Box<dyn std::error::Error>> {
+    match &event.log {
+        Some(x) => Ok(Some(x.open(&event.key(), execution_type.phase())?)),
+        None => Ok(None),
+    }
+}
+fn spawn(
+    what: &str,
+    event: &Event,
+    execution_type: &ExecutionType,
+    builder: &mut Command,
+) -> Result<Spawned, Box<dyn std::error::Error>> {
+    let started = Local::now();
+    let stopwatch = Instant::now();
+    let child = match builder.spawn() {
+        Ok(x) => x,
+        Err(err) => {
+            // Recorded, so it's known the script was due even though it never ran
+            let execution = history::Execution::failed_to_spawn(
+                &event.key(),
+                execution_type.phase(),
+                what,
+                &started,
+                &err.to_string(),
+            );
+            if let Err(err) = history::append(&execution) {
+                error!("Couldn't write execution to history: {}", err);
+            }
+            return Err(format!("Couldn't spawn {}: {}", what, err).into());
+        }
+    };
+    Decision::new("command_spawned")
+        .event(&event.key())
+        .phase(execution_type.phase())
+        .field("command", what)
+        .field("pid", child.id())
+        .log(Level::Info, &format!("Spawned {} (pid {})", what, child.id()));
+    Ok(Spawned {
+        child,
+        started,
+        stopwatch,
+    })
+}
+/// Logs and records in the history exit of the spawned command, returns error if it failed
+/// Command killed by the signal it was stopped with (e.g. stop_signal of a service) didn't fail
+pub fn exited(
+    what: &str,
+    event: &Event,
+    execution_type: &ExecutionType,
+    spawned: &Spawned,
+    status: ExitStatus,
+    stderr: String,
+    stopped_with: Option<Signal>,
+) -> Result<ExecutionOutput, Box<dyn std::error::Error>> {
+    let duration = spawned.elapsed();
+    // Exit code is missing only when the script was terminated by a signal
+    let (code, signal) = match status.code() {
+        Some(code) => (code, None),
+        None => {
+            let signal = status.signal().unwrap();
+            (128 + signal, Some(signal_name(signal)))
+        }
+    };
+    Decision::new("command_exited")
+        .event(&event.key())
+        .phase(execution_type.phase())
+        .field("command", what)
+        .field("exit_code", code)
+        .field("signal", signal.to_owned())
+        .log(Level::Info, &format!("{} exited with code {}", what, code));
+    let execution = history::Execution::new(
+        &event.key(),
+        execution_type.phase(),
+        what,
+        &spawned.started,
+        &duration,
+        code,
+        &signal,
+    );
+    if let Err(err) = history::append(&execution) {
+        error!("Couldn't write execution to history: {}", err);
+    }
+    let failed = match &signal {
+        Some(_) if stopped_with.is_some() && status.signal() == stopped_with.map(|x| x as i32) => false,
+        Some(name) if event.signal_success() => {
+            warn!("Execution killed by signal {}", name);
+            false
+        }
+        Some(name) => {
+            error!("Execution killed by signal {}", name);
+            true
+        }
+        None if event.is_failure_code(code) => {
+            error!("Execution failed with code {}", code);
+            true
+        }
+        None => false,
+    };
+    if failed {
+        let err = ExecutionError {
+            code,
+            signal,
+            stderr,
+        };
         return Err(err.into());
     }
-    Ok(())
+    Ok(ExecutionOutput {
+        code,
+        signal,
+        stderr,
+    })
 }
-fn pipe_to() -> Result<Stdio, Box<dyn std::error::Error>> {
-    match &CONFIG.pipe_to {
-        &config::PipeTo::FILE => Ok(OpenOptions::new()
-            .write(true)
-            .append(true)
-            .create(true)
-            .open(CONFIG.file.as_ref().unwrap())?
-            .into()),
-        &config::PipeTo::STDERR => unsafe { Ok(Stdio::from_raw_fd(stderr().as_raw_fd())) },
-        &config::PipeTo::STDOUT => Ok(Stdio::inherit()),
-        &config::PipeTo::NONE => Ok(Stdio::null()),
+/// Returns name of the signal (e.g. SIGKILL), or its number if it's unknown
+fn signal_name(signal: i32) -> String {
+    match Signal::try_from(signal) {
+        Ok(x) => x.as_str().to_owned(),
+        Err(_) => signal.to_string(),
     }
 }
+/// Builds a command from the given string, with its output going where the settings send it
+pub fn build_command(what: &str) -> Result<Command, Box<dyn std::error::Error>> {
+    build_command_to(what, &Output::from_settings())
+}
+/// Builds a command from the given string, with its output going to the given destinations
+pub fn build_command_to(what: &str, output: &Output) -> Result<Command, Box<dyn std::error::Error>> {
+    let mut split = what.split(" ");
+    let mut builder = Command::new(split.next().unwrap());
+    builder.args(split);
+    output.apply(&mut builder)?;
+    Ok(builder)
+}
@@ -0,0 +1,122 @@
+use crate::errors::BadAttributeValue;
+use std::convert::TryFrom;
+use yaml_rust::Yaml;
+
+/// Set of exit codes, given in yaml as a list of codes and inclusive ranges (e.g. [0, 3, "10-20"])
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExitCodes {
+    ranges: Vec<(i32, i32)>,
+}
+impl ExitCodes {
+    /// Reads exit codes from the given attribute of an event
+    /// Returns None if the attribute is missing
+    pub fn new(
+        yaml_object: &Yaml,
+        attribute: &str,
+        weekday: &str,
+    ) -> Result<Option<Self>, BadAttributeValue> {
+        let items = match &yaml_object[attribute] {
+            Yaml::BadValue => return Ok(None),
+            Yaml::Array(x) => x.to_owned(),
+            x => vec![x.to_owned()],
+        };
+        let ranges = items
+            .iter()
+            .map(|item| parse_range(item).ok_or(BadAttributeValue::new(attribute, weekday)))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Some(Self { ranges }))
+    }
+    pub fn contains(&self, code: i32) -> bool {
+        self.ranges
+            .iter()
+            .any(|(from, to)| from <= &code && &code <= to)
+    }
+}
+/// Parses single code (e.g. 3) or range of codes (e.g. "10-20")
+fn parse_range(item: &Yaml) -> Option<(i32, i32)> {
+    match item {
+        Yaml::Integer(x) => {
+            let code = i32::try_from(*x).ok()?;
+            Some((code, code))
+        }
+        Yaml::String(x) => {
+            let mut split = x.splitn(2, '-');
+            let from = split.next()?.trim().parse::<i32>().ok()?;
+            let to = match split.next() {
+                Some(to) => to.trim().parse::<i32>().ok()?,
+                None => from,
+            };
+            if from > to {
+                return None;
+            }
+            Some((from, to))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::Event;
+    use std::borrow::Cow;
+    use yaml_rust::YamlLoader;
+
+    fn yaml(attributes: &str) -> Yaml {
+        YamlLoader::load_from_str(&format!(
+            "{{start: \"10:00\", end: \"11:00\", execute_start: /bin/true, execute_end: /bin/true, {}}}",
+            attributes
+        ))
+        .unwrap()
+        .remove(0)
+    }
+    fn codes(attributes: &str) -> Result<Option<ExitCodes>, BadAttributeValue> {
+        ExitCodes::new(&yaml(attributes), "success_codes", "monday")
+    }
+    fn failures(attributes: &str, codes: &[i32]) -> Vec<bool> {
+        let event = Event::new(&yaml(attributes), &Cow::Borrowed("monday")).unwrap();
+        codes.iter().map(|x| event.is_failure_code(*x)).collect()
+    }
+
+    #[test]
+    fn reads_lists_and_ranges_of_codes() {
+        assert!(codes("other: 1").unwrap().is_none());
+        let list = codes("success_codes: [0, 3]").unwrap().unwrap();
+        assert_eq!(list.ranges, vec![(0, 0), (3, 3)]);
+        assert_eq!(codes("success_codes: 4").unwrap().unwrap().ranges, vec![(4, 4)]);
+        let ranges = codes("success_codes: [\"10-20\", \" 30 - 30 \", \"40\"]").unwrap().unwrap();
+        assert_eq!(ranges.ranges, vec![(10, 20), (30, 30), (40, 40)]);
+        assert!(ranges.contains(10) && ranges.contains(20) && ranges.contains(40));
+        assert!(!ranges.contains(9) && !ranges.contains(21));
+    }
+    #[test]
+    fn rejects_invalid_codes() {
+        assert!(codes("success_codes: [\"20-10\"]").is_err());
+        assert!(codes("success_codes: [\"ten\"]").is_err());
+        assert!(codes("success_codes: [1.5]").is_err());
+        // Doesn't fit an exit code, instead of being truncated to 0
+        assert!(codes("success_codes: [4294967296]").is_err());
+    }
+    #[test]
+    fn fails_on_codes_not_in_success_codes() {
+        assert_eq!(failures("success_codes: [0, 3]", &[0, 1, 3]), vec![false, true, false]);
+    }
+    #[test]
+    fn fails_on_failure_codes_only() {
+        assert_eq!(
+            failures("failure_codes: [2, \"5-7\"]", &[0, 1, 2, 4, 6]),
+            vec![false, false, true, false, true]
+        );
+    }
+    #[test]
+    fn fails_on_codes_in_failure_codes_even_if_successful() {
+        assert_eq!(
+            failures("success_codes: \"0-10\", failure_codes: [5]", &[0, 5, 11]),
+            vec![false, true, true]
+        );
+    }
+    #[test]
+    fn fails_on_codes_greater_than_fail_on_code_without_codes() {
+        assert_eq!(failures("fail_on_code: 1", &[0, 1, 2]), vec![false, false, true]);
+    }
+}
@@ -0,0 +1,174 @@
+use crate::event_cache::state_file_path;
+use chrono::{offset::Local, DateTime, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::time::Duration;
+const HISTORY_FILE_NAME: &str = "ontime.history";
+// Exit code recorded for scripts which couldn't be spawned, the same shells use for missing commands
+const SPAWN_FAILED_CODE: i32 = 127;
+
+/// Single execution of an event's script, stored as a json line in the append-only history file
+/// Unlike the cache, history isn't cleaned on a new day
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Execution {
+    pub date: String,
+    pub event: String,
+    pub phase: String,
+    pub command: String,
+    // Time the script was spawned at, in RFC 3339 format
+    pub start: String,
+    // Duration of the execution (in seconds)
+    pub duration: f64,
+    pub exit_code: i32,
+    // Name of the signal that killed the script
+    pub signal: Option<String>,
+    // Why the script couldn't be spawned, its exit code is SPAWN_FAILED_CODE then
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+impl Execution {
+    pub fn new(
+        event: &str,
+        phase: &str,
+        command: &str,
+        start: &DateTime<Local>,
+        duration: &Duration,
+        exit_code: i32,
+        signal: &Option<String>,
+    ) -> Self {
+        Self {
+            date: start.date().naive_local().to_string(),
+            event: event.to_owned(),
+            phase: phase.to_owned(),
+            command: command.to_owned(),
+            start: start.to_rfc3339(),
+            duration: duration.as_secs_f64(),
+            exit_code,
+            signal: signal.to_owned(),
+            error: None,
+        }
+    }
+    /// Execution of a script which couldn't be spawned at all (e.g. it's missing)
+    pub fn failed_to_spawn(
+        event: &str,
+        phase: &str,
+        command: &str,
+        start: &DateTime<Local>,
+        error: &str,
+    ) -> Self {
+        let mut execution = Self::new(
+            event,
+            phase,
+            command,
+            start,
+            &Duration::default(),
+            SPAWN_FAILED_CODE,
+            &None,
+        );
+        execution.error = Some(error.to_owned());
+        execution
+    }
+    /// Whether the execution belongs to the given event, given by its key or by its command
+    /// Command matches whole, by its program (e.g. /usr/bin/bell) or by the file name of the program (e.g. bell)
+    fn matches(&self, event: &str) -> bool {
+        let program = self.command.split(' ').next().unwrap_or_default();
+        self.event == event
+            || self.command == event
+            || program == event
+            || Path::new(program).file_name().is_some_and(|x| x == event)
+    }
+}
+
+/// Appends the execution to the history file
+pub fn append(execution: &Execution) -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(state_file_path(HISTORY_FILE_NAME)?)?;
+    let mut line = serde_json::to_string(execution)?;
+    line.push('\n');
+    file.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Prints executions from the history, optionally only since the given date and of the given event (see Execution::matches)
+pub fn print(since: &Option<NaiveDate>, event: &Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+    let path = state_file_path(HISTORY_FILE_NAME)?;
+    let file = match OpenOptions::new().read(true).open(&path) {
+        Ok(x) => x,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    for line in io::BufReader::new(file).lines() {
+        let line = line?;
+        let execution: Execution = match serde_json::from_str(&line) {
+            Ok(x) => x,
+            Err(err) => {
+                warn!("Skipping malformed history entry ({}): {}", err, line);
+                continue;
+            }
+        };
+        if let Some(since) = since {
+            // Dates are in YYYY-MM-DD format, so they compare the same as strings
+            if execution.date < since.to_string() {
+                continue;
+            }
+        }
+        if let Some(event) = event {
+            if !execution.matches(event) {
+                continue;
+            }
+        }
+        let status = match (&execution.error, &execution.signal) {
+            (Some(error), _) => format!("couldn't spawn ({})", error),
+            (None, Some(signal)) => format!("killed by {}", signal),
+            (None, None) => format!("exit {}", execution.exit_code),
+        };
+        writeln!(
+            stdout,
+            "{} {} {} {:.3}s {} {}",
+            execution.start,
+            execution.event,
+            execution.phase,
+            execution.duration,
+            status,
+            execution.command
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_event_by_key_or_command() {
+        let execution = Execution::new(
+            "5d41402abc4b2a76b9719d911017c592",
+            "start",
+            "/usr/local/bin/bell.sh --loud",
+            &Local::now(),
+            &Duration::from_secs(1),
+            0,
+            &None,
+        );
+        assert!(execution.matches("5d41402abc4b2a76b9719d911017c592"));
+        assert!(execution.matches("/usr/local/bin/bell.sh --loud"));
+        assert!(execution.matches("/usr/local/bin/bell.sh"));
+        assert!(execution.matches("bell.sh"));
+        assert!(!execution.matches("bell"));
+        assert!(!execution.matches("--loud"));
+    }
+    #[test]
+    fn reads_entries_without_error() {
+        let line = r#"{"date":"2026-10-19","event":"bell","phase":"start","command":"bell.sh","start":"2026-10-19T10:00:00+00:00","duration":0.5,"exit_code":0,"signal":null}"#;
+        let execution: Execution = serde_json::from_str(line).unwrap();
+        assert_eq!(execution.error, None);
+        assert!(!serde_json::to_string(&execution).unwrap().contains("error"));
+    }
+}
@@ -0,0 +1,90 @@
+use crate::errors::ExecutionError;
+use crate::event::ExecutionType;
+use crate::event_processor::{build_command, ExecutionOutput};
+use crate::output;
+use crate::settings;
+use crate::signals;
+use yaml_rust::Yaml;
+
+// Longest standard error passed to a hook, so it always fits the environment
+const MAX_STDERR_LENGTH: usize = 4096;
+
+/// Commands run after execution of start, end or during script
+/// They receive ONTIME_PHASE (start, end or during), ONTIME_EXIT_CODE, ONTIME_SIGNAL and ONTIME_STDERR environment variables
+/// ONTIME_STDERR holds only the last MAX_STDERR_LENGTH bytes of standard error, without NUL bytes
+/// Standard error of services isn't captured, so it's always empty for them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hooks {
+    // Override --on-success and --on-failure
+    on_success: Option<String>,
+    on_failure: Option<String>,
+}
+impl Hooks {
+    /// Reads on_success and on_failure attributes of an event
+    pub fn new(yaml_object: &Yaml) -> Self {
+        Self {
+            on_success: yaml_object["on_success"].to_owned().into_string(),
+            on_failure: yaml_object["on_failure"].to_owned().into_string(),
+        }
+    }
+    fn on_success(&self) -> Option<String> {
+        self.on_success.to_owned().or_else(|| settings::get().on_success.to_owned())
+    }
+    fn on_failure(&self) -> Option<String> {
+        self.on_failure.to_owned().or_else(|| settings::get().on_failure.to_owned())
+    }
+    /// Whether any hook is going to be run, so the output should be captured
+    pub fn is_set(&self) -> bool {
+        self.on_success().is_some() || self.on_failure().is_some()
+    }
+    /// Runs on_success or on_failure hook, depending on the result of the execution
+    /// Failure of the hook itself is only logged
+    pub fn run(
+        &self,
+        key: &str,
+        execution_type: &ExecutionType,
+        result: &Result<ExecutionOutput, Box<dyn std::error::Error>>,
+    ) {
+        let (hook, code, signal, stderr) = match result {
+            Ok(x) => (self.on_success(), x.code.to_string(), x.signal.to_owned(), x.stderr.to_owned()),
+            Err(err) => match err.downcast_ref::<ExecutionError>() {
+                Some(x) => (self.on_failure(), x.code.to_string(), x.signal.to_owned(), x.stderr.to_owned()),
+                // Command couldn't be executed at all, so there is no exit code
+                None => (self.on_failure(), String::new(), None, err.to_string()),
+            },
+        };
+        let hook = match hook {
+            Some(x) => x,
+            None => return,
+        };
+        debug!("Running hook: {}", hook);
+        let status = build_command(&hook).and_then(|mut builder| {
+            builder
+                .env("ONTIME_PHASE", execution_type.phase())
+                .env("ONTIME_EXIT_CODE", code)
+                .env("ONTIME_SIGNAL", signal.unwrap_or_default())
+                .env("ONTIME_STDERR", stderr_variable(&stderr));
+            let mut child = output::spawn(&mut builder, key)?;
+            let _watchdog = signals::kill_on_shutdown(child.id());
+            Ok(child.wait()?)
+        });
+        match status {
+            Ok(x) if !x.success() => error!("Hook {} failed ({})", hook, x),
+            Err(err) => error!("Hook {} failed: {}", hook, err),
+            _ => {}
+        }
+    }
+}
+/// Returns the end of the standard error, which can be passed in an environment variable
+fn stderr_variable(stderr: &str) -> String {
+    // NUL byte would make spawning the hook fail
+    let stderr = stderr.replace('\0', "");
+    if stderr.len() <= MAX_STDERR_LENGTH {
+        return stderr;
+    }
+    let mut start = stderr.len() - MAX_STDERR_LENGTH;
+    while !stderr.is_char_boundary(start) {
+        start += 1;
+    }
+    stderr[start..].to_owned()
+}
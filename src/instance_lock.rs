@@ -0,0 +1,53 @@
+use crate::errors::AlreadyRunningError;
+use crate::event_cache::cache_file_path;
+use nix::errno::Errno;
+use nix::fcntl::{flock, FlockArg};
+use std::fs;
+use std::io::{Read, Seek, Write};
+use std::os::unix::io::AsRawFd;
+
+/// Exclusive advisory lock on the pidfile, held for the whole life of the daemon
+/// The pidfile is locked instead of the cache, as the cache is replaced on every write
+/// It's kept next to the cache (e.g. ontime.cache.pid), so instances sharing the cache exclude each other whatever their state directories are
+/// Lock is released by the kernel when the process exits, so a stale pidfile never blocks a new instance
+#[derive(Debug)]
+pub struct InstanceLock {
+    // Never read, the lock lives as long as the descriptor is open
+    _descriptor: fs::File,
+}
+impl InstanceLock {
+    /// Locks the pidfile and writes the current process id to it
+    /// If another instance holds the lock, fails or blocks until the lock is released when wait is set
+    pub fn acquire(wait: bool) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut path = cache_file_path()?.into_os_string();
+        path.push(".pid");
+        let mut descriptor = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            // Truncated only once locked, the pid of a running instance is reported otherwise
+            .truncate(false)
+            .open(&path)?;
+        // EAGAIN is the same as EWOULDBLOCK on Linux
+        match flock(descriptor.as_raw_fd(), FlockArg::LockExclusiveNonblock) {
+            Ok(()) => (),
+            Err(nix::Error::Sys(Errno::EAGAIN)) if wait => {
+                info!("Another instance of ontime is running, waiting for it to exit");
+                flock(descriptor.as_raw_fd(), FlockArg::LockExclusive)?;
+            }
+            Err(nix::Error::Sys(Errno::EAGAIN)) => {
+                let mut pid = String::new();
+                descriptor.read_to_string(&mut pid)?;
+                return Err(Box::new(AlreadyRunningError::new(&pid)));
+            }
+            Err(err) => return Err(Box::new(err)),
+        }
+        descriptor.set_len(0)?;
+        descriptor.seek(std::io::SeekFrom::Start(0))?;
+        writeln!(descriptor, "{}", std::process::id())?;
+        debug!("Locked {:?}", path);
+        Ok(Self {
+            _descriptor: descriptor,
+        })
+    }
+}
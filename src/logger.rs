@@ -0,0 +1,110 @@
+use crate::config::{LogFormat, LogTo};
+use crate::syslog::SyslogLogger;
+use chrono::{offset::Local, TimeZone};
+use log::{Level, Log, Record};
+use pretty_env_logger::env_logger::{fmt::Formatter, Builder, Env};
+use serde_json::{Map, Value};
+use std::cell::RefCell;
+use std::io::{self, Write};
+
+const DECISION_TARGET: &str = "ontime::decision";
+
+thread_local! {
+    // Decision being logged, so the formatter can attach its fields to the record
+    static DECISION: RefCell<Option<Decision>> = const { RefCell::new(None) };
+}
+
+/// Initializes logger writing to the given destination, in the given format if it's standard error
+/// Text format is configured by RUST_LOG, json format and syslog also respect it but log decisions by default
+pub fn init(log_to: &LogTo, format: &LogFormat) -> Result<(), Box<dyn std::error::Error>> {
+    match (log_to, format) {
+        (LogTo::SYSLOG, _) => {
+            log::set_boxed_logger(Box::new(SyslogLogger::new()?))?;
+            log::set_max_level(log::LevelFilter::Trace);
+        }
+        (LogTo::STDERR, LogFormat::TEXT) => pretty_env_logger::init(),
+        (LogTo::STDERR, LogFormat::JSON) => {
+            Builder::from_env(Env::default().default_filter_or("info"))
+                .format(format_json)
+                .init()
+        }
+    }
+    Ok(())
+}
+/// Calls the closure with the decision currently being logged, if any
+pub fn with_decision<T, F: FnOnce(Option<&Decision>) -> T>(f: F) -> T {
+    DECISION.with(|decision| f(decision.borrow().as_ref()))
+}
+
+/// Scheduler decision (e.g. event being due or command being spawned)
+/// Its fields are logged along with the message in json format and to syslog
+#[derive(Debug, Clone)]
+pub struct Decision {
+    kind: &'static str,
+    fields: Map<String, Value>,
+}
+impl Decision {
+    pub fn new(kind: &'static str) -> Self {
+        Self {
+            kind,
+            fields: Map::new(),
+        }
+    }
+    pub fn kind(&self) -> &'static str {
+        self.kind
+    }
+    pub fn fields(&self) -> &Map<String, Value> {
+        &self.fields
+    }
+    pub fn event(self, event: &str) -> Self {
+        self.field("event", event)
+    }
+    pub fn phase(self, phase: &str) -> Self {
+        self.field("phase", phase)
+    }
+    /// Adds timestamp field, converted to RFC 3339 format
+    pub fn time(self, name: &str, timestamp: i64) -> Self {
+        let time = Local.timestamp(timestamp, 0).to_rfc3339();
+        self.field(name, time)
+    }
+    pub fn field<T: Into<Value>>(mut self, name: &str, value: T) -> Self {
+        self.fields.insert(name.to_owned(), value.into());
+        self
+    }
+    /// Logs the message with decision fields attached
+    pub fn log(self, level: Level, message: &str) {
+        if level <= log::max_level() {
+            self.log_to(log::logger(), level, message);
+        }
+    }
+    /// Logs the message with decision fields attached, through the given logger instead of the global one
+    pub fn log_to(self, logger: &dyn Log, level: Level, message: &str) {
+        DECISION.with(|decision| *decision.borrow_mut() = Some(self));
+        logger.log(
+            &Record::builder()
+                .args(format_args!("{}", message))
+                .level(level)
+                .target(DECISION_TARGET)
+                .build(),
+        );
+        DECISION.with(|decision| decision.borrow_mut().take());
+    }
+}
+
+/// Formats record as a single line json object
+fn format_json(buf: &mut Formatter, record: &Record) -> io::Result<()> {
+    let mut object = Map::new();
+    object.insert("timestamp".into(), Local::now().to_rfc3339().into());
+    object.insert("level".into(), record.level().to_string().into());
+    object.insert("target".into(), record.target().into());
+    object.insert("message".into(), record.args().to_string().into());
+    if record.target() == DECISION_TARGET {
+        with_decision(|decision| {
+            if let Some(decision) = decision {
+                object.insert("decision".into(), decision.kind.into());
+                object.extend(decision.fields.to_owned());
+            }
+        });
+    }
+    writeln!(buf, "{}", Value::Object(object))
+}
@@ -8,22 +8,35 @@ extern crate lazy_static;
 extern crate clap;
 
 mod config;
+mod config_watcher;
 mod directory;
 mod errors;
 mod event;
 mod event_cache;
 mod event_list;
+mod event_log;
 mod event_processor;
+mod exit_codes;
+mod history;
+mod hooks;
+mod instance_lock;
+mod logger;
+mod output;
 mod plan;
+mod service;
+mod settings;
+mod signals;
+mod syslog;
 mod weekday;
 
-use crate::directory::{filter_dir_content, read_env_dir_or_fallback_to_etc};
+use crate::config_watcher::ConfigWatcher;
+use crate::directory::{config_dirs, filter_dir_content, read_config_dirs};
 use crate::event::ExecutionType;
 use crate::event_processor::process_event;
 use chrono::{offset::Local, Datelike};
 use clap::Clap;
 use std::path;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use weekday::chrono_to_string;
 
 const APP_NAME: &'static str = "ontime";
@@ -33,67 +46,153 @@ lazy_static! {
 }
 
 fn main() {
-    pretty_env_logger::init();
     // Initialize CONFIG early to parse the config
-    {
-        &CONFIG.distance;
+    if let Err(err) = logger::init(&CONFIG.log_to, &CONFIG.log_format) {
+        eprintln!("Couldn't initialize logger: {}", err);
+        std::process::exit(1);
     }
-    match real_main() {
-        Err(err) => {
-            error!("{:?}", err);
-        }
-        _ => {
-            error!("Weird, this shouldn't end");
+    if CONFIG.fail.is_some() {
+        warn!("--fail is deprecated, use --on-fail");
+    }
+    if CONFIG.pipe.is_some_and(|x| x.deprecated) {
+        warn!("--pipe with flags is deprecated, use names, e.g. --pipe stdout,stderr");
+    }
+    if let Some(config::Command::History(args)) = &CONFIG.command {
+        if let Err(err) = history::print(&args.since, &args.event) {
+            error!("{}", err);
+            std::process::exit(1);
         }
+        return;
+    }
+    // Returns only after a graceful shutdown, requested by SIGTERM or SIGINT
+    if let Err(err) = real_main() {
+        error!("{}", err);
+        std::process::exit(1);
     }
-    std::process::exit(1);
 }
 fn real_main() -> Result<(), Box<dyn std::error::Error>> {
-    let dir_content = read_env_dir_or_fallback_to_etc(
-        "XDG_CONFIG_HOME",
-        APP_NAME,
-        Some(filter_dir_content),
-    )?;
+    // Held until the process exits, so two instances never execute the same events
+    let _lock = instance_lock::InstanceLock::acquire(CONFIG.wait)?;
+    signals::install()?;
+    let dirs = config_dirs(APP_NAME, &CONFIG.config_dir);
+    let dir_content = read_config_dirs(&dirs, Some(filter_dir_content))?;
     debug!("Found files: {:?}", dir_content);
-    time_loop(&dir_content)?;
+    settings::load(&dir_content)?;
+    time_loop(&dirs, dir_content)?;
     return Ok(());
 }
-fn time_loop(conf_files: &Vec<path::PathBuf>) -> Result<(), Box<dyn std::error::Error>> {
-    let mut plan = plan::get_plan(&Local::now(), conf_files, None)?;
+fn time_loop(
+    dirs: &Vec<path::PathBuf>,
+    mut conf_files: Vec<path::PathBuf>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut watcher = watch(dirs);
+    let mut plan = plan::get_plan(&Local::now(), &conf_files, None)?;
     loop {
         let now = Local::now();
         if plan.weekday != chrono_to_string(&now.weekday()) {
-            plan = plan::get_plan(&now, conf_files, Some(plan))?;
+            plan = plan::get_plan(&now, &conf_files, Some(plan))?;
+        } else {
+            match watcher.as_ref().map(|x| x.changed()) {
+                Some(Ok(true)) => reload(dirs, &mut conf_files, &mut plan),
+                // Changes could have been missed, so the plan is reloaded after the watcher is created again
+                Some(Err(err)) => {
+                    error!("Couldn't watch plans for changes ({}), watching them again", err);
+                    watcher = watch(dirs);
+                    reload(dirs, &mut conf_files, &mut plan);
+                }
+                _ => (),
+            }
         }
         if plan.events.head.is_none() {
-            std::thread::sleep(Duration::from_secs(60));
+            signals::sleep(Duration::from_secs(60));
         }
         let mut current_link = plan.events.head.as_mut();
         let mut changed = false;
         // Iterate until there are no more elements (links) in the list
         while let Some(current) = current_link {
+            // No new executions are started once shutdown is requested
+            if signals::shutdown_requested() {
+                break;
+            }
             let execution = &current.value.should_execute(&now.timestamp());
-            match execution {
-                ExecutionType::NONE => {}
+            let result = match execution {
+                ExecutionType::NONE => Ok(()),
                 ExecutionType::LOOP => {
                     process_event(
                         &mut current.value,
                         &execution,
-                    )?
+                    )
                 }
                 _ => {
                     changed = true;
                     process_event(
                         &mut current.value,
                        &execution,
-                    )?
+                    )
                 }
+            };
+            // Failed execution shouldn't stop other events from being executed
+            if let Err(err) = result {
+                error!("{}", err);
             }
             current_link = current.next_mut();
         }
         if changed {
             plan.cache.write(&plan.events)?;
         }
-        std::thread::sleep(Duration::from_secs(60));
+        // Sleep until the next check, reloading the plan if SIGHUP is received in the meantime
+        let next_check = Instant::now() + Duration::from_secs(60);
+        while let Some(left) = next_check.checked_duration_since(Instant::now()) {
+            if signals::shutdown_requested() {
+                break;
+            }
+            signals::sleep(left);
+            if signals::reload_requested() {
+                info!("Received SIGHUP, reloading plan");
+                reload(dirs, &mut conf_files, &mut plan);
+            }
+        }
+        if signals::shutdown_requested() {
+            break;
+        }
+    }
+    info!("Shutting down");
+    // Services are stopped, but stay started in the cache, so they're spawned again on the next run
+    let mut current_link = plan.events.head.as_ref();
+    while let Some(current) = current_link {
+        if let Some(service) = &current.value.service {
+            if let Err(err) = service::stop(&current.value, service) {
+                error!("Couldn't stop service {}: {}", service.run, err);
+            }
+        }
+        current_link = current.next_ref();
+    }
+    plan.cache.write(&plan.events)?;
+    Ok(())
+}
+/// Plan is still executed without hot reload, if the directories can't be watched
+fn watch(dirs: &[path::PathBuf]) -> Option<ConfigWatcher> {
+    match ConfigWatcher::new(dirs) {
+        Ok(x) => Some(x),
+        Err(err) => {
+            warn!("Couldn't watch plans for changes: {}", err);
+            None
+        }
+    }
+}
+/// Reads the directories with plans again, then rebuilds the current plan and the settings
+/// Broken plan or settings (e.g. saved in the middle of editing) keep the previous ones running
+fn reload(dirs: &Vec<path::PathBuf>, conf_files: &mut Vec<path::PathBuf>, plan: &mut plan::Plan) {
+    let reloaded = read_config_dirs(dirs, Some(filter_dir_content)).and_then(|files| {
+        let reloaded_plan = plan::reload_plan(&Local::now(), &files, plan)?;
+        settings::load(&files)?;
+        Ok((reloaded_plan, files))
+    });
+    match reloaded {
+        Ok((x, files)) => {
+            *plan = x;
+            *conf_files = files;
+        }
+        Err(err) => error!("Couldn't reload plan: {}", err),
     }
 }
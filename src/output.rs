@@ -0,0 +1,192 @@
+use crate::config::{Destination, Pipe, PipeTo};
+use crate::settings;
+use crate::syslog::send_output;
+use nix::unistd::dup;
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+
+/// Destinations of both streams of the executed command's output
+/// Inherit and combined are resolved, so every destination is none, stdout, stderr, a file or syslog
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Output {
+    pub stdout: Destination,
+    pub stderr: Destination,
+}
+impl Output {
+    /// Resolves destinations given by stdout_to and stderr_to
+    /// Stream without its destination goes where pipe and pipe_to send it
+    pub fn new(
+        stdout_to: Option<Destination>,
+        stderr_to: Option<Destination>,
+        pipe: Pipe,
+        pipe_to: &PipeTo,
+        file: &Option<PathBuf>,
+    ) -> Self {
+        let piped = match (pipe_to, file) {
+            (PipeTo::STDOUT, _) => Destination::STDOUT,
+            (PipeTo::STDERR, _) => Destination::STDERR,
+            (PipeTo::NONE, _) => Destination::NONE,
+            (PipeTo::FILE, Some(x)) => Destination::FILE(x.to_owned()),
+            (PipeTo::FILE, None) => {
+                warn!("Piping to a file requires --file, output isn't piped");
+                Destination::INHERIT
+            }
+        };
+        let stdout = stdout_to.unwrap_or_else(|| match pipe.stdout {
+            true => piped.to_owned(),
+            false => Destination::INHERIT,
+        });
+        let stderr = stderr_to.unwrap_or_else(|| match pipe.stderr {
+            true => piped.to_owned(),
+            false => Destination::INHERIT,
+        });
+        let stdout = match stdout {
+            Destination::INHERIT => Destination::STDOUT,
+            x => x,
+        };
+        let stderr = match stderr {
+            Destination::INHERIT => Destination::STDERR,
+            x => x,
+        };
+        // Both streams being combined with each other is the same as inheriting them
+        let (stdout, stderr) = match (stdout, stderr) {
+            (Destination::COMBINED, Destination::COMBINED) => (Destination::STDOUT, Destination::STDERR),
+            (Destination::COMBINED, x) => (x.to_owned(), x),
+            (x, Destination::COMBINED) => (x.to_owned(), x),
+            x => x,
+        };
+        Self { stdout, stderr }
+    }
+    /// Output according to the settings only, e.g. for hooks and services
+    pub fn from_settings() -> Self {
+        let settings = settings::get();
+        Self::new(
+            settings.stdout_to.to_owned(),
+            settings.stderr_to.to_owned(),
+            settings.pipe,
+            &settings.pipe_to,
+            &settings.file,
+        )
+    }
+    /// Sets standard output and error of the command, streams going to syslog are piped
+    pub fn apply(&self, builder: &mut Command) -> Result<(), Box<dyn std::error::Error>> {
+        builder.stdout(stdio(&self.stdout)?);
+        builder.stderr(stdio(&self.stderr)?);
+        Ok(())
+    }
+}
+fn stdio(destination: &Destination) -> Result<Stdio, Box<dyn std::error::Error>> {
+    match destination {
+        Destination::NONE => Ok(Stdio::null()),
+        // Duplicate the descriptor, as Stdio closes it once the command is spawned
+        Destination::STDOUT => unsafe { Ok(Stdio::from_raw_fd(dup(io::stdout().as_raw_fd())?)) },
+        Destination::STDERR => unsafe { Ok(Stdio::from_raw_fd(dup(io::stderr().as_raw_fd())?)) },
+        Destination::FILE(path) => Ok(OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)?
+            .into()),
+        Destination::SYSLOG => Ok(Stdio::piped()),
+        // Resolved by Output::new
+        Destination::INHERIT | Destination::COMBINED => Ok(Stdio::inherit()),
+    }
+}
+/// Writes captured output to its destination, used when the stream had to be captured (e.g. for hooks)
+pub fn forward(
+    content: &[u8],
+    destination: &Destination,
+    event: &str,
+    stream: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match destination {
+        Destination::NONE => (),
+        Destination::STDOUT => io::stdout().write_all(content)?,
+        Destination::FILE(path) => OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)?
+            .write_all(content)?,
+        Destination::SYSLOG => {
+            for line in content.split(|x| *x == b'\n').filter(|x| !x.is_empty()) {
+                send_output(event, stream, &String::from_utf8_lossy(line));
+            }
+        }
+        Destination::STDERR | Destination::INHERIT | Destination::COMBINED => {
+            io::stderr().write_all(content)?
+        }
+    }
+    Ok(())
+}
+/// Sends every line of the stream to syslog, until the stream is closed
+pub fn copy_to_syslog<R: Read>(stream: R, event: &str, name: &str) -> io::Result<()> {
+    for line in BufReader::new(stream).split(b'\n') {
+        send_output(event, name, &String::from_utf8_lossy(&line?));
+    }
+    Ok(())
+}
+/// Spawns the command, copying its piped streams to syslog on separate threads
+/// Streams are piped only when they go to syslog, see Output::apply
+pub fn spawn(builder: &mut Command, event: &str) -> io::Result<Child> {
+    let mut child = builder.spawn()?;
+    copy_to_syslog_detached(&mut child, event);
+    Ok(child)
+}
+/// Copies piped streams of the spawned command to syslog on separate threads, until the command closes them
+pub fn copy_to_syslog_detached(child: &mut Child, event: &str) {
+    if let Some(stdout) = child.stdout.take() {
+        let event = event.to_owned();
+        thread::spawn(move || copy_to_syslog(stdout, &event, "stdout"));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        let event = event.to_owned();
+        thread::spawn(move || copy_to_syslog(stderr, &event, "stderr"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to(name: &str) -> Destination {
+        name.parse::<Destination>().unwrap()
+    }
+
+    #[test]
+    fn resolves_destinations_of_both_streams() {
+        let file = Some(PathBuf::from("/var/log/ontime.log"));
+        let both = Pipe {
+            stdout: true,
+            stderr: true,
+        };
+        // stdout_to, stderr_to, pipe, pipe_to, --file, resulting stdout and stderr
+        let cases = [
+            (Some("none"), Some("./err.log"), Pipe::STDOUT, PipeTo::STDERR, &None, ("none", "./err.log")),
+            (Some("combined"), Some("combined"), both, PipeTo::NONE, &None, ("stdout", "stderr")),
+            (Some("combined"), Some("./err.log"), Pipe::STDOUT, PipeTo::STDERR, &None, ("./err.log", "./err.log")),
+            (Some("syslog"), Some("combined"), Pipe::STDOUT, PipeTo::STDERR, &None, ("syslog", "syslog")),
+            (Some("inherit"), Some("inherit"), both, PipeTo::NONE, &None, ("stdout", "stderr")),
+            (None, None, Pipe::STDOUT, PipeTo::STDERR, &None, ("stderr", "stderr")),
+            (None, None, both, PipeTo::NONE, &None, ("none", "none")),
+            (None, None, Pipe::default(), PipeTo::NONE, &None, ("stdout", "stderr")),
+            (None, Some("stdout"), both, PipeTo::FILE, &file, ("/var/log/ontime.log", "stdout")),
+            // Piping to a file without --file inherits the streams
+            (None, None, both, PipeTo::FILE, &None, ("stdout", "stderr")),
+        ];
+        for (stdout_to, stderr_to, pipe, pipe_to, file, (stdout, stderr)) in cases.iter() {
+            let output = Output::new(stdout_to.map(to), stderr_to.map(to), *pipe, pipe_to, file);
+            assert_eq!(
+                (output.stdout, output.stderr),
+                (to(stdout), to(stderr)),
+                "{:?} and {:?} with {:?} to {:?}",
+                stdout_to,
+                stderr_to,
+                pipe,
+                pipe_to
+            );
+        }
+    }
+}
@@ -1,13 +1,19 @@
 use crate::errors;
 use crate::event::Event;
 use crate::event_cache::Cache;
+use crate::directory::is_drop_in;
 use crate::event_list::{EventList, ListElement};
-use crate::weekday::chrono_to_string;
-use chrono::Datelike;
-use std::ops::Deref;
-use std::path::PathBuf;
-use std::borrow::{Borrow, Cow};
-use yaml_rust::YamlLoader;
+use crate::logger::Decision;
+use crate::weekday::{chrono_to_string, parse_days};
+use chrono::{Datelike, Weekday};
+use log::Level;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use yaml_rust::{yaml, Yaml, YamlLoader};
+const MAIN_FILE_STEM: &str = "main";
+
 #[derive(Debug)]
 pub struct Plan {
     pub weekday: Cow<'static, str>,
@@ -15,55 +21,265 @@ pub struct Plan {
     pub cache: Cache,
 }
 
-// Finds plan for current day and returns it
-pub fn get_plan(
+/// Weekday of a plan, files it was read from and its events
+type PlanEvents = (Cow<'static, str>, Vec<PathBuf>, Vec<Event>);
+
+/// Reads events of the current day from every plan file of the day, returns the weekday and the files they were read from too
+/// Files are merged from the least to the most important: main.yml, weekday files (monday.yml, see PlanFile) and drop-in files (conf.d/*.yml), each in name order
+/// Weekday files go from the least to the most specific (weekdays.yml, wed-fri.yml, friday.yml), like keys of main.yml
+/// Drop-in files are read the same way as main.yml, e.g. so every team can keep its own file for the whole week
+/// Event of a more important file replaces the event with the same identity of a less important one, which is reported
+fn read_events(
     time_now: &chrono::DateTime<chrono::Local>,
     conf_files: &Vec<PathBuf>,
-    previous_plan: Option<Plan>,
-) -> Result<Plan, Box<dyn std::error::Error>> {
+) -> Result<PlanEvents, Box<dyn std::error::Error>> {
     let str_weekday = chrono_to_string(&time_now.weekday());
-    // TODO: Move to a helper function
-    let mut file_name = conf_files.into_iter().find(|&file| {
-        file.to_str()
-            .unwrap()
-            .contains::<&str>(str_weekday.borrow())
-    });
-    let is_main = if file_name.is_none() {
-        file_name = conf_files
-            .into_iter()
-            .find(|&file| return file.to_str().unwrap().contains("main"));
-        if file_name.is_none() {
-            return Err(Box::new(errors::PlanNotFoundError::new(str_weekday)));
+    let mut main_files = Vec::new();
+    let mut weekday_files = Vec::new();
+    let mut drop_in_files = Vec::new();
+    for file in conf_files {
+        match PlanFile::of(file) {
+            PlanFile::MAIN => main_files.push((file, None)),
+            PlanFile::WEEKDAY(x) if x.contains(&time_now.weekday()) => {
+                weekday_files.push((file, Some(x.len())))
+            }
+            PlanFile::DROPIN => drop_in_files.push((file, None)),
+            _ => (),
+        }
+    }
+    for files in &mut [&mut main_files, &mut weekday_files, &mut drop_in_files] {
+        files.sort_by_key(|(file, days)| (Reverse(*days), file.file_name().map(|x| x.to_owned())));
+    }
+    let files = main_files
+        .into_iter()
+        .chain(weekday_files)
+        .chain(drop_in_files)
+        .collect::<Vec<(&PathBuf, Option<usize>)>>();
+    if files.is_empty() {
+        return Err(Box::new(errors::PlanNotFoundError::new(str_weekday)));
+    }
+    let mut events = Vec::new();
+    let mut found = false;
+    // Files whose events were taken, shared by all files of the day
+    let mut read = Vec::new();
+    for (index, (file, file_days)) in files.iter().enumerate() {
+        let source = load_file(
+            file,
+            time_now.weekday(),
+            *file_days,
+            &mut Vec::new(),
+            &mut read,
+        )?;
+        // Weekday file contains only array with events, main.yml and drop-in files may lack the weekday
+        if !source.found && file_days.is_some() {
+            return Err(Box::new(errors::RequiredAttributeMissingError::new(
+                "array of events",
+                &str_weekday,
+            )));
         }
-        true
-    } else {
-        false
+        found |= source.found;
+        // Build vector of events from yaml array
+        for (element, days) in &source.events {
+            let element = apply_template(element, &source.templates, &str_weekday)?;
+            let mut event = Event::new(&element, &str_weekday)?;
+            event.calculate_checksum();
+            events.push((event, days.to_owned(), index));
+        }
+    }
+    if !found {
+        return Err(Box::new(errors::PlanNotFoundError::new(str_weekday)));
+    }
+    let files = files
+        .into_iter()
+        .map(|(x, _)| x.to_owned())
+        .collect::<Vec<PathBuf>>();
+    let events = override_events(events, &files);
+    warn_duplicate_keys(&events);
+    Ok((str_weekday, files, events))
+}
+/// Kind of a plan file, given by its stem (file name without .yml or .yaml)
+/// stem := "main" | days | days "-" label
+/// days := the same as keys of main.yml (monday, mon, monday-friday, weekend, mon,wed), see weekday::parse_days
+/// label := any text (e.g. monday-backups or mon-fri-backups)
+/// Stem naming days is never read as a label, so monday-friday is a range, like it is in main.yml
+/// Every yaml file in a drop-in directory (conf.d) is a drop-in file, whatever its name
+#[derive(Debug, PartialEq)]
+enum PlanFile {
+    MAIN,
+    // Days the file is for
+    WEEKDAY(Vec<Weekday>),
+    DROPIN,
+    // Not a plan, e.g. ontime.yml with settings
+    NONE,
+}
+impl PlanFile {
+    fn of(file: &Path) -> Self {
+        if is_drop_in(file) {
+            return PlanFile::DROPIN;
+        }
+        let stem = match file.file_stem().map(|x| x.to_str()) {
+            Some(Some(x)) => x,
+            Some(None) => {
+                warn!("Name of {:?} isn't valid UTF-8, it's ignored", file);
+                return PlanFile::NONE;
+            }
+            None => return PlanFile::NONE,
+        };
+        if stem == MAIN_FILE_STEM {
+            return PlanFile::MAIN;
+        }
+        if let Some(days) = parse_days(stem) {
+            return PlanFile::WEEKDAY(days);
+        }
+        // Label follows the longest part naming days
+        let mut days_part = stem;
+        while let Some(position) = days_part.rfind('-') {
+            days_part = &days_part[..position];
+            if let Some(days) = parse_days(days_part) {
+                return PlanFile::WEEKDAY(days);
+            }
+        }
+        debug!("{:?} isn't a plan file", file);
+        PlanFile::NONE
+    }
+}
+/// Events and templates read from a plan file and the files it includes
+#[derive(Debug, Default)]
+struct Source {
+    // Events with the number of days covered by the key they're under, or by the name of the weekday file
+    events: Vec<(Yaml, usize)>,
+    templates: yaml::Hash,
+    // Whether any of the files had events of the day
+    found: bool,
+}
+/// Reads events of the weekday and templates from the plan file, then from the files it includes
+/// File is either main.yml, with events under keys naming days, or a weekday file with an array of events
+/// Weekday file is given with the number of days its name covers (file_days), which is the specificity of its events
+/// Keys of main.yml are weekdays (monday or mon), ranges (monday-friday), groups (weekdays, weekend, everyday)
+/// or comma separated lists of them (mon,wed,fri), see weekday::parse_days
+/// Weekday file can also be a hash with events under the events key, so it can include files and define templates
+/// Included files (include: common.yml or a list of them) are relative to the including file and are read the same way as it
+/// Templates of the including file take precedence over the included ones
+/// Events of a file are taken only once, even if it's both included and read on its own (e.g. a drop-in), its templates are read every time
+fn load_file(
+    path: &Path,
+    day: Weekday,
+    file_days: Option<usize>,
+    including: &mut Vec<PathBuf>,
+    read: &mut Vec<PathBuf>,
+) -> Result<Source, Box<dyn std::error::Error>> {
+    let weekday = chrono_to_string(&day);
+    let canonical = std::fs::canonicalize(path)?;
+    if including.contains(&canonical) {
+        return Err(format!("{:?} includes itself", path).into());
+    }
+    including.push(canonical.to_owned());
+    // Only the first document of the file is read
+    let yaml = YamlLoader::load_from_str(&std::fs::read_to_string(path)?)?
+        .into_iter()
+        .next()
+        .unwrap_or(Yaml::BadValue);
+    let mut source = Source::default();
+    let includes = match &yaml["include"] {
+        Yaml::BadValue => Vec::new(),
+        Yaml::String(x) => vec![x.to_owned()],
+        Yaml::Array(x) => x
+            .iter()
+            .map(|x| x.to_owned().into_string())
+            .collect::<Option<Vec<String>>>()
+            .ok_or(errors::BadAttributeValue::new("include", &weekday))?,
+        _ => return Err(Box::new(errors::BadAttributeValue::new("include", &weekday))),
     };
-    let yaml = YamlLoader::load_from_str(&std::fs::read_to_string(file_name.unwrap())?)?;
-    // If file is main.y(a)ml, a weekday should be yaml property
-    let array =
-        if is_main {
-            yaml[0][str_weekday.as_ref()].as_vec().ok_or(
-                errors::RequiredAttributeMissingError::new(str_weekday.deref(), "main"),
-            )?
-        // Else if file is a seperate weekday, it contains only array with events
-        } else {
-            yaml[0]
+    for include in includes {
+        let included_path = path.with_file_name(&include);
+        debug!("Including {:?}", included_path);
+        let included = load_file(&included_path, day, file_days, including, read)?;
+        source.events.extend(included.events);
+        source.templates.extend(included.templates);
+        source.found |= included.found;
+    }
+    if let Some(templates) = yaml["templates"].as_hash() {
+        source.templates.extend(templates.to_owned());
+    }
+    let mut events = Vec::new();
+    let mut found = false;
+    let weekday_events = match (&yaml, file_days) {
+        (Yaml::Array(x), Some(days)) => Some((x, days)),
+        (_, Some(days)) => yaml["events"].as_vec().map(|x| (x, days)),
+        (_, None) => None,
+    };
+    if let Some((weekday_events, days)) = weekday_events {
+        events.extend(weekday_events.iter().map(|x| (x.to_owned(), days)));
+        found = true;
+    }
+    if let (Some(hash), None) = (yaml.as_hash(), file_days) {
+        for (key, value) in hash {
+            let key = key.as_str().unwrap_or_default();
+            if key == "include" || key == "templates" {
+                continue;
+            }
+            let days = match parse_days(key) {
+                Some(x) => x,
+                None => {
+                    warn!("Key {} in {:?} doesn't name days, it's ignored", key, path);
+                    continue;
+                }
+            };
+            if !days.contains(&day) {
+                continue;
+            }
+            let day_events = value
                 .as_vec()
-                .ok_or(errors::RequiredAttributeMissingError::new(
-                    "array of events",
-                    &str_weekday,
-                ))?
-        };
-    // Build vector of events from yaml array
-    let mut events = array
-        .iter()
-        .map(|element| -> Result<Event, Box<dyn std::error::Error>> {
-            let mut event = Event::new(element, &str_weekday)?;
-            event.calculate_checksum();
-            Ok(event)
-        })
-        .collect::<Result<Vec<Event>, _>>()?;
+                .ok_or(errors::RequiredAttributeMissingError::new("array of events", key))?;
+            events.extend(day_events.iter().map(|x| (x.to_owned(), days.len())));
+            found = true;
+        }
+    }
+    if found {
+        source.found = true;
+        if read.contains(&canonical) {
+            warn!("Events of {:?} were already read, they're taken only once", path);
+        } else {
+            read.push(canonical);
+            source.events.extend(events);
+        }
+    }
+    including.pop();
+    Ok(source)
+}
+/// Returns the event with attributes of the template given by use attribute, attributes of the event take precedence
+fn apply_template(
+    element: &Yaml,
+    templates: &yaml::Hash,
+    weekday: &str,
+) -> Result<Yaml, Box<dyn std::error::Error>> {
+    let name = match &element["use"] {
+        Yaml::BadValue => return Ok(element.to_owned()),
+        x => x
+            .as_str()
+            .ok_or(errors::BadAttributeValue::new("use", weekday))?,
+    };
+    let mut merged = templates
+        .get(&Yaml::String(name.to_owned()))
+        .and_then(|x| x.as_hash())
+        .ok_or(errors::TemplateNotFoundError::new(name, weekday))?
+        .to_owned();
+    if let Some(attributes) = element.as_hash() {
+        for (key, value) in attributes {
+            if key.as_str() != Some("use") {
+                merged.insert(key.to_owned(), value.to_owned());
+            }
+        }
+    }
+    Ok(Yaml::Hash(merged))
+}
+// Finds plan for current day and returns it
+pub fn get_plan(
+    time_now: &chrono::DateTime<chrono::Local>,
+    conf_files: &Vec<PathBuf>,
+    previous_plan: Option<Plan>,
+) -> Result<Plan, Box<dyn std::error::Error>> {
+    let (str_weekday, files, mut events) = read_events(time_now, conf_files)?;
     if let Some(previous) = previous_plan {
         let mut vec = previous
             .events
@@ -71,24 +287,325 @@ pub fn get_plan(
             .filter(|element| element.should_reschedule()).collect::<Vec<Event>>();
         events.append(&mut vec);
     }
+    build_plan(time_now, str_weekday, &files, events)
+}
+/// Rebuilds plan for current day, after its files have changed
+/// Execution state is kept by event identity, events removed after their start are kept until their end (e.g. so their service is stopped)
+pub fn reload_plan(
+    time_now: &chrono::DateTime<chrono::Local>,
+    conf_files: &Vec<PathBuf>,
+    previous_plan: &Plan,
+) -> Result<Plan, Box<dyn std::error::Error>> {
+    let (str_weekday, files, mut events) = read_events(time_now, conf_files)?;
+    let mut previous_events = previous_plan.events.clone().into_iter().collect::<Vec<Event>>();
+    let mut added = Vec::new();
+    let mut changed = Vec::new();
+    for event in events.iter_mut() {
+        let key = event.key();
+        match previous_events.iter().position(|x| x.key() == key) {
+            Some(position) => {
+                let previous = previous_events.remove(position);
+                if previous.to_string() != event.to_string() {
+                    changed.push(key);
+                }
+                event.keep_state_of(&previous);
+            }
+            None => added.push(key),
+        }
+    }
+    let removed = previous_events.iter().map(|x| x.key()).collect::<Vec<String>>();
+    events.extend(previous_events.into_iter().filter(|x| x.should_reschedule()));
+    Decision::new("plan_reloaded")
+        .field("added", added.to_owned())
+        .field("removed", removed.to_owned())
+        .field("changed", changed.to_owned())
+        .log(
+            Level::Info,
+            &format!(
+                "Plan changed, added: {:?}, removed: {:?}, changed: {:?}",
+                added, removed, changed
+            ),
+        );
+    build_plan(time_now, str_weekday, &files, events)
+}
+/// Builds list of the events and reads their execution state from the cache
+fn build_plan(
+    time_now: &chrono::DateTime<chrono::Local>,
+    str_weekday: Cow<'static, str>,
+    files: &[PathBuf],
+    mut events: Vec<Event>,
+) -> Result<Plan, Box<dyn std::error::Error>> {
+    let event_count = events.len();
     // Sorts from the first to the last event (by start key)
     events.sort();
     // Reverse the vector
     // Insert all events by adding to an end of the list, first_link contains first element (last in events vector)
-    let mut first_link = ListElement::new(events.pop().unwrap());
-    // Reverse the vector
-    events.reverse();
-    let mut current_link = &mut first_link;
-    for event in events {
-        current_link = current_link.push(ListElement::new(event)).unwrap();
-    }
-    let mut list = EventList::new_with_head(first_link);
+    let mut list = match events.pop() {
+        Some(first) => {
+            let mut first_link = ListElement::new(first);
+            // Reverse the vector
+            events.reverse();
+            let mut current_link = &mut first_link;
+            for event in events {
+                current_link = current_link.push(ListElement::new(event)).unwrap();
+            }
+            EventList::new_with_head(first_link)
+        }
+        // Plan without events, e.g. emptied by a reload
+        None => EventList { head: None },
+    };
     let mut cache = Cache::initial_read_cache()?;
     cache.cleanup(&time_now.date().naive_local())?;
     cache.full_read_cache(&mut list)?;
+    Decision::new("plan_loaded")
+        .field("weekday", str_weekday.as_ref())
+        .field(
+            "files",
+            files
+                .iter()
+                .map(|x| x.to_string_lossy().into_owned())
+                .collect::<Vec<String>>(),
+        )
+        .field("events", event_count)
+        .log(
+            Level::Info,
+            &format!("Loaded plan for {} with {} events", str_weekday, event_count),
+        );
     return Ok(Plan {
         weekday: str_weekday,
         events: list,
         cache,
     });
 }
+/// Merges events of the files, given with the number of days their key covers and the index of their file
+/// Event of a later (more important) file replaces events with the same identity of the earlier ones
+/// Within a file, events of a more specific key replace the ones of a less specific key
+/// Specificity is given by the number of days the key covers, e.g. monday overrides mon,wed which overrides weekdays
+/// Events under equally specific keys of the same file are all kept
+fn override_events(events: Vec<(Event, usize, usize)>, files: &[PathBuf]) -> Vec<Event> {
+    let mut kept: Vec<(Event, usize, usize)> = Vec::new();
+    for (event, days, file) in events {
+        let key = event.key();
+        if let Some((_, _, overridden)) = kept.iter().find(|(x, _, y)| x.key() == key && *y != file) {
+            let overridden = overridden.to_owned();
+            Decision::new("event_overridden")
+                .event(&key)
+                .field("file", files[file].to_string_lossy().as_ref())
+                .field("overridden_file", files[overridden].to_string_lossy().as_ref())
+                .log(
+                    Level::Warn,
+                    &format!(
+                        "Event {} of {:?} overrides the one of {:?}",
+                        key, files[file], files[overridden]
+                    ),
+                );
+            kept.retain(|(x, _, _)| x.key() != key);
+        }
+        match kept.iter().position(|(x, _, _)| x.key() == key) {
+            Some(position) if kept[position].1 > days => {
+                debug!("Event {} overrides the one of a less specific key", key);
+                kept[position] = (event, days, file);
+            }
+            Some(position) if kept[position].1 < days => {
+                debug!("Event {} is overridden by the one of a more specific key", key);
+            }
+            _ => kept.push((event, days, file)),
+        }
+    }
+    kept.into_iter().map(|(x, _, _)| x).collect()
+}
+/// Warns about events sharing the same identity, as they share the executed state in the cache too
+fn warn_duplicate_keys(events: &Vec<Event>) {
+    let mut keys = HashSet::new();
+    for event in events {
+        let key = event.key();
+        if !keys.insert(key.to_owned()) {
+            warn!(
+                "Multiple events are identified as {}, give them distinct ids to track them separately",
+                key
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn event(id: &str, run: &str) -> Event {
+        let yaml = YamlLoader::load_from_str(&format!(
+            "{{id: {}, start: \"10:00\", end: \"11:00\", execute_start: {}, execute_end: /bin/true}}",
+            id, run
+        ))
+        .unwrap()
+        .remove(0);
+        Event::new(&yaml, &Cow::Borrowed("monday")).unwrap()
+    }
+    fn runs(events: Vec<Event>) -> Vec<String> {
+        events.into_iter().map(|x| x.execute_start).collect()
+    }
+    // Creates empty directory for files of the test
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ontime-plan-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("inc")).unwrap();
+        dir
+    }
+    fn load(path: &Path, read: &mut Vec<PathBuf>) -> Result<Source, Box<dyn std::error::Error>> {
+        load_file(path, Weekday::Mon, None, &mut Vec::new(), read)
+    }
+    fn ids(source: &Source) -> Vec<&str> {
+        source.events.iter().map(|(x, _)| x["id"].as_str().unwrap()).collect()
+    }
+    fn template(yaml: &str) -> Result<Yaml, Box<dyn std::error::Error>> {
+        let templates = YamlLoader::load_from_str(
+            "{backup: {start: \"10:00\", execute_start: /bin/backup, execute_end: /bin/true}}",
+        )
+        .unwrap()
+        .remove(0);
+        let element = YamlLoader::load_from_str(yaml).unwrap().remove(0);
+        apply_template(&element, templates.as_hash().unwrap(), "monday")
+    }
+
+    #[test]
+    fn names_plan_files_like_keys_of_main() {
+        let of = |x: &str| PlanFile::of(Path::new(x));
+        assert_eq!(of("/etc/ontime/main.yml"), PlanFile::MAIN);
+        assert_eq!(of("/home/domain/ontime/monday.yml"), PlanFile::WEEKDAY(vec![Weekday::Mon]));
+        assert_eq!(of("mon.yaml"), PlanFile::WEEKDAY(vec![Weekday::Mon]));
+        assert_eq!(of("monday-backups.yml"), PlanFile::WEEKDAY(vec![Weekday::Mon]));
+        assert_eq!(
+            of("monday-wednesday.yml"),
+            PlanFile::WEEKDAY(vec![Weekday::Mon, Weekday::Tue, Weekday::Wed])
+        );
+        assert_eq!(
+            of("sat-sun-backups.yml"),
+            PlanFile::WEEKDAY(vec![Weekday::Sat, Weekday::Sun])
+        );
+        assert_eq!(of("weekend.yml"), PlanFile::WEEKDAY(vec![Weekday::Sat, Weekday::Sun]));
+        assert_eq!(of("monday_backup.yml"), PlanFile::NONE);
+        assert_eq!(of("ontime.yml"), PlanFile::NONE);
+        assert_eq!(of("conf.d/monday_team.yml"), PlanFile::DROPIN);
+    }
+    #[test]
+    fn more_specific_key_overrides_in_any_order() {
+        let files = vec![PathBuf::from("main.yml")];
+        let events = vec![
+            (event("a", "monday"), 1, 0),
+            (event("a", "weekdays"), 5, 0),
+            (event("a", "monwed"), 2, 0),
+        ];
+        assert_eq!(runs(override_events(events, &files)), vec!["monday"]);
+    }
+    #[test]
+    fn equally_specific_keys_are_kept() {
+        let files = vec![PathBuf::from("main.yml")];
+        let events = vec![(event("a", "first"), 2, 0), (event("a", "second"), 2, 0)];
+        assert_eq!(runs(override_events(events, &files)), vec!["first", "second"]);
+    }
+    #[test]
+    fn later_file_overrides_regardless_of_specificity() {
+        let files = vec![PathBuf::from("main.yml"), PathBuf::from("conf.d/team.yml")];
+        let events = vec![
+            (event("a", "main"), 1, 0),
+            (event("b", "kept"), 1, 0),
+            (event("a", "team"), 7, 1),
+        ];
+        assert_eq!(runs(override_events(events, &files)), vec!["kept", "team"]);
+    }
+    #[test]
+    fn includes_files_relative_to_the_including_one() {
+        let dir = test_dir("include");
+        std::fs::write(
+            dir.join("main.yml"),
+            "include: inc/common.yml\ntemplates: {t: {end: \"12:00\"}}\nmonday: [{id: own}]\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("inc/common.yml"),
+            "templates: {t: {end: \"11:00\"}, u: {}}\nweekdays: [{id: included}]\n",
+        )
+        .unwrap();
+        let source = load(&dir.join("main.yml"), &mut Vec::new()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(ids(&source), vec!["included", "own"]);
+        assert_eq!(source.events[0].1, 5);
+        // Templates of the including file take precedence
+        let templates = Yaml::Hash(source.templates);
+        assert_eq!(templates["t"]["end"].as_str(), Some("12:00"));
+        assert!(templates["u"].as_hash().is_some());
+    }
+    #[test]
+    fn fails_on_file_including_itself() {
+        let dir = test_dir("cycle");
+        std::fs::write(dir.join("main.yml"), "include: main.yml\nmonday: []\n").unwrap();
+        std::fs::write(dir.join("a.yml"), "include: inc/b.yml\n").unwrap();
+        std::fs::write(dir.join("inc/b.yml"), "include: ../a.yml\n").unwrap();
+        let itself = load(&dir.join("main.yml"), &mut Vec::new());
+        let through = load(&dir.join("a.yml"), &mut Vec::new());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(itself.unwrap_err().to_string().contains("includes itself"));
+        assert!(through.unwrap_err().to_string().contains("includes itself"));
+    }
+    #[test]
+    fn takes_events_of_a_file_only_once() {
+        let dir = test_dir("once");
+        std::fs::write(dir.join("main.yml"), "include: inc/team.yml\nmonday: [{id: own}]\n").unwrap();
+        std::fs::write(dir.join("inc/team.yml"), "templates: {t: {}}\nmonday: [{id: team}]\n").unwrap();
+        let mut read = Vec::new();
+        let main = load(&dir.join("main.yml"), &mut read).unwrap();
+        let team = load(&dir.join("inc/team.yml"), &mut read).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(ids(&main), vec!["team", "own"]);
+        assert!(team.events.is_empty());
+        assert!(team.found);
+        // Templates are read every time
+        assert_eq!(team.templates.len(), 1);
+    }
+    #[test]
+    fn attributes_of_the_event_override_its_template() {
+        let event = template("{use: backup, execute_start: /bin/own, end: \"11:00\"}").unwrap();
+        assert_eq!(event["execute_start"].as_str(), Some("/bin/own"));
+        assert_eq!(event["execute_end"].as_str(), Some("/bin/true"));
+        assert_eq!(event["start"].as_str(), Some("10:00"));
+        assert_eq!(event["end"].as_str(), Some("11:00"));
+        assert!(event["use"].is_badvalue());
+        let plain = template("{execute_start: /bin/plain}").unwrap();
+        assert_eq!(plain["execute_start"].as_str(), Some("/bin/plain"));
+    }
+    #[test]
+    fn fails_on_missing_template() {
+        let missing = template("{use: restore}").unwrap_err();
+        assert!(missing.is::<errors::TemplateNotFoundError>());
+        let malformed = template("{use: [backup]}").unwrap_err();
+        assert!(malformed.is::<errors::BadAttributeValue>());
+    }
+    #[test]
+    fn more_specific_weekday_file_overrides_regardless_of_name() {
+        let dir = test_dir("weekday");
+        let write = |name: &str| {
+            let event = format!(
+                "[{{id: a, start: \"10:00\", end: \"11:00\", execute_start: {}, execute_end: /bin/true}}]",
+                name
+            );
+            std::fs::write(dir.join(name), event).unwrap();
+            dir.join(name)
+        };
+        let weekdays = write("weekdays.yml");
+        let friday = write("friday.yml");
+        let wed_fri = write("wed-fri.yml");
+        // Friday, 23 October 2026
+        let time_now = chrono::Local.ymd(2026, 10, 23).and_hms(12, 0, 0);
+        let all = read_events(
+            &time_now,
+            &vec![friday.to_owned(), weekdays.to_owned(), wed_fri.to_owned()],
+        );
+        let ranges = read_events(&time_now, &vec![weekdays.to_owned(), wed_fri.to_owned()]);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let (_, files, events) = all.unwrap();
+        assert_eq!(files, vec![weekdays, wed_fri, friday]);
+        assert_eq!(runs(events), vec!["friday.yml"]);
+        assert_eq!(runs(ranges.unwrap().2), vec!["wed-fri.yml"]);
+    }
+}
@@ -0,0 +1,252 @@
+use crate::errors::BadAttributeValue;
+use crate::event::{Event, ExecutionType};
+use crate::event_processor::{exited, spawn_detached, Spawned};
+use crate::signals;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use yaml_rust::Yaml;
+
+// Time given to a service to exit after being signalled, before it gets killed
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+// Delay of the first restart, doubled with every restart of a service exiting soon after its start
+const RESTART_DELAY: Duration = Duration::from_secs(10);
+// Longest delay between restarts, service running longer than it is restarted without a delay again
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(600);
+
+lazy_static! {
+    // Running services, by the key of the event they belong to
+    static ref RUNNING: Mutex<HashMap<String, Running>> = Mutex::new(HashMap::new());
+}
+
+/// Spawned service with the state of its restarts
+#[derive(Debug)]
+struct Running {
+    spawned: Spawned,
+    // Phase the service was spawned in, during when it was restarted
+    execution_type: ExecutionType,
+    // Whether the exit was noticed and recorded
+    exited: bool,
+    // Restarts in a row, each after the service exited soon after its start
+    restarts: u32,
+    // Time of the next restart, set once the exit is noticed
+    restart_at: Option<Instant>,
+}
+impl Running {
+    fn new(spawned: Spawned, execution_type: ExecutionType, restarts: u32) -> Self {
+        Running {
+            spawned,
+            execution_type,
+            exited: false,
+            restarts,
+            restart_at: None,
+        }
+    }
+}
+
+/// What to do when a service exits before its event ends
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RestartPolicy {
+    NEVER,
+    FAILURE,
+    ALWAYS,
+}
+impl std::str::FromStr for RestartPolicy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(Self::NEVER),
+            "on-failure" => Ok(Self::FAILURE),
+            "always" => Ok(Self::ALWAYS),
+            _ => Err("Value not allowed".into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Service {
+    // Command spawned on start and kept running until end
+    pub run: String,
+    pub restart: RestartPolicy,
+    // Signal sent to the service on end
+    pub stop_signal: Signal,
+}
+impl Service {
+    /// Reads service attributes (run, restart and stop_signal) of an event
+    /// Returns None if the event doesn't have run field
+    pub fn new(
+        yaml_object: &Yaml,
+        weekday: &str,
+    ) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        let run = match yaml_object["run"].as_str() {
+            Some(x) => x.to_owned(),
+            None => return Ok(None),
+        };
+        let restart = match yaml_object["restart"].as_str() {
+            Some(x) => RestartPolicy::from_str(x)
+                .map_err(|_| BadAttributeValue::new("restart", weekday))?,
+            None => RestartPolicy::NEVER,
+        };
+        let stop_signal = match yaml_object["stop_signal"].as_str() {
+            Some(x) => parse_signal(x).ok_or(BadAttributeValue::new("stop_signal", weekday))?,
+            None => Signal::SIGTERM,
+        };
+        Ok(Some(Service {
+            run,
+            restart,
+            stop_signal,
+        }))
+    }
+}
+/// Parses signal name, with or without SIG prefix (e.g. SIGTERM or TERM)
+fn parse_signal(name: &str) -> Option<Signal> {
+    let name = name.to_uppercase();
+    if name.starts_with("SIG") {
+        Signal::from_str(&name).ok()
+    } else {
+        Signal::from_str(&format!("SIG{}", name)).ok()
+    }
+}
+
+/// Spawns the service and remembers it under the key of its event
+/// Service that couldn't be spawned runs the failure hook, like a start script
+pub fn start(event: &Event, service: &Service) -> Result<(), Box<dyn std::error::Error>> {
+    let key = event.key();
+    let mut running = RUNNING.lock().unwrap();
+    if running.contains_key(&key) {
+        return Ok(());
+    }
+    let spawned = match spawn_detached(&service.run, event, &ExecutionType::START) {
+        Ok(x) => x,
+        Err(err) => {
+            let message = err.to_string();
+            event.hooks.run(&key, &ExecutionType::START, &Err(err));
+            return Err(message.into());
+        }
+    };
+    running.insert(key, Running::new(spawned, ExecutionType::START, 0));
+    Ok(())
+}
+/// Checks whether the service is still running and restarts it according to its restart policy
+/// Exit of the service is recorded and passed to hooks once it's noticed on a check, under the phase it was spawned in (start, or during if it was restarted)
+/// Service that isn't known (e.g. ontime was restarted after the start) gets spawned
+pub fn supervise(event: &Event, service: &Service) -> Result<(), Box<dyn std::error::Error>> {
+    let key = event.key();
+    let mut running = RUNNING.lock().unwrap();
+    let current = match running.get_mut(&key) {
+        Some(x) => x,
+        None => {
+            drop(running);
+            return start(event, service);
+        }
+    };
+    if !current.exited {
+        let status = match current.spawned.child.try_wait()? {
+            Some(status) => status,
+            None => return Ok(()),
+        };
+        current.exited = true;
+        let result = exited(
+            &service.run,
+            event,
+            &current.execution_type,
+            &current.spawned,
+            status,
+            String::new(),
+            None,
+        );
+        event.hooks.run(&key, &current.execution_type, &result);
+        let restart = match service.restart {
+            RestartPolicy::ALWAYS => true,
+            RestartPolicy::FAILURE => result.is_err(),
+            RestartPolicy::NEVER => false,
+        };
+        // Without a restart the exited child is kept, so the service isn't spawned again
+        if !restart {
+            return Ok(());
+        }
+        // Service crashing on start is restarted with a growing delay, instead of being spawned over and over
+        if current.spawned.elapsed() > MAX_RESTART_DELAY {
+            current.restarts = 0;
+        }
+        let delay = restart_delay(current.restarts);
+        warn!(
+            "Service {} exited ({}), restarting in {}s",
+            service.run,
+            status,
+            delay.as_secs()
+        );
+        current.restart_at = Some(Instant::now() + delay);
+    }
+    match current.restart_at {
+        Some(x) if Instant::now() >= x => (),
+        _ => return Ok(()),
+    }
+    let restarts = current.restarts + 1;
+    let spawned = spawn_detached(&service.run, event, &ExecutionType::LOOP)?;
+    running.insert(key, Running::new(spawned, ExecutionType::LOOP, restarts));
+    Ok(())
+}
+/// Delay before the restart, after the given number of restarts in a row
+fn restart_delay(restarts: u32) -> Duration {
+    RESTART_DELAY
+        .checked_mul(2u32.saturating_pow(restarts))
+        .map_or(MAX_RESTART_DELAY, |x| std::cmp::min(x, MAX_RESTART_DELAY))
+}
+/// Sends the stop signal to the service and waits for it to exit
+/// Service not exiting within STOP_TIMEOUT (or by the shutdown deadline) gets killed
+/// Its exit is recorded and passed to hooks under the end phase, being killed by stop_signal isn't a failure
+pub fn stop(event: &Event, service: &Service) -> Result<(), Box<dyn std::error::Error>> {
+    let key = event.key();
+    let mut current = match RUNNING.lock().unwrap().remove(&key) {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+    if current.exited {
+        return Ok(());
+    }
+    let child = &mut current.spawned.child;
+    // Service which exited on its own before it was stopped is recorded under the phase it was spawned in
+    let (status, execution_type) = match child.try_wait()? {
+        Some(status) => (status, &current.execution_type),
+        None => {
+            debug!(
+                "Stopping service {} with {}",
+                service.run,
+                service.stop_signal.as_str()
+            );
+            kill(Pid::from_raw(child.id() as i32), service.stop_signal)?;
+            let mut deadline = Instant::now() + STOP_TIMEOUT;
+            // Services stopped on shutdown share its deadline, instead of each getting the whole STOP_TIMEOUT
+            if let Some(x) = signals::shutdown_deadline() {
+                deadline = std::cmp::min(deadline, x);
+            }
+            let status = loop {
+                if let Some(status) = child.try_wait()? {
+                    break status;
+                }
+                if Instant::now() >= deadline {
+                    warn!("Service {} didn't stop in time, killing it", service.run);
+                    child.kill()?;
+                    break child.wait()?;
+                }
+                std::thread::sleep(Duration::from_millis(100));
+            };
+            (status, &ExecutionType::END)
+        }
+    };
+    let result = exited(
+        &service.run,
+        event,
+        execution_type,
+        &current.spawned,
+        status,
+        String::new(),
+        Some(service.stop_signal),
+    );
+    event.hooks.run(&key, execution_type, &result);
+    result.map(|_| ())
+}
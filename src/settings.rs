@@ -0,0 +1,153 @@
+use crate::config::{yaml_names, Destination, OnFail, Pipe, PipeTo};
+use crate::directory::is_drop_in;
+use crate::CONFIG;
+use std::convert::TryFrom;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use yaml_rust::{Yaml, YamlLoader};
+const SETTINGS_FILE_STEM: &str = "ontime";
+
+lazy_static! {
+    // Settings from the command line only, until the settings file is read
+    static ref SETTINGS: RwLock<Arc<Settings>> = RwLock::new(Arc::new(
+        Settings::new(&Yaml::BadValue).expect("Settings without a file are always valid")
+    ));
+}
+
+/// Settings given either on the command line or in ontime.yml (or ontime.yaml) in the directory with plans
+/// Keys of the file are named like the options, with underscores (e.g. fail_on_code)
+/// Command line takes precedence over the file, the file is read again whenever the plans are reloaded
+#[derive(Debug)]
+pub struct Settings {
+    pub distance: i64,
+    pub distance_start: Option<i64>,
+    pub distance_end: Option<i64>,
+    pub on_fail: OnFail,
+    pub retries: u32,
+    pub retry_delay: i64,
+    pub retry_backoff: i64,
+    pub on_success: Option<String>,
+    pub on_failure: Option<String>,
+    pub fail_on_code: i32,
+    pub signal_success: bool,
+    pub pipe: Pipe,
+    pub pipe_to: PipeTo,
+    pub file: Option<PathBuf>,
+    pub stdout_to: Option<Destination>,
+    pub stderr_to: Option<Destination>,
+}
+impl Settings {
+    fn new(yaml: &Yaml) -> Result<Self, Box<dyn std::error::Error>> {
+        let int = |key: &str| -> Result<Option<i64>, String> {
+            match &yaml[key] {
+                Yaml::BadValue => Ok(None),
+                x => x.as_i64().map(Some).ok_or(not_allowed(key)),
+            }
+        };
+        // Integers which can't be negative, e.g. retry_delay
+        let unsigned = |key: &str| -> Result<Option<i64>, String> {
+            Ok(fitting::<u32>(int(key)?, key)?.map(i64::from))
+        };
+        let string = |key: &str| -> Result<Option<String>, String> {
+            match &yaml[key] {
+                Yaml::BadValue => Ok(None),
+                x => x.to_owned().into_string().map(Some).ok_or(not_allowed(key)),
+            }
+        };
+        let names = |key: &str| -> Result<Option<String>, String> {
+            match &yaml[key] {
+                Yaml::BadValue => Ok(None),
+                x => yaml_names(x).map(Some).ok_or(not_allowed(key)),
+            }
+        };
+        let on_fail = match names("on_fail")? {
+            Some(x) => Some(x.parse::<OnFail>().map_err(|err| setting_error("on_fail", &err))?),
+            None => None,
+        };
+        // Deprecated fail key with flags, superseded by on_fail
+        let fail = fitting::<u32>(int("fail")?, "fail")?.map(OnFail::from_flags);
+        if fail.is_some() {
+            warn!("Setting fail in the settings file is deprecated, use on_fail");
+        }
+        let pipe = match names("pipe")? {
+            Some(x) => Some(x.parse::<Pipe>().map_err(|err| setting_error("pipe", &err))?),
+            None => None,
+        };
+        let pipe_to = match string("pipe_to")? {
+            Some(x) => Some(x.parse::<PipeTo>().map_err(|_| not_allowed("pipe_to"))?),
+            None => None,
+        };
+        let destination = |key: &str| -> Result<Option<Destination>, String> {
+            match string(key)? {
+                Some(x) => Ok(Some(x.parse::<Destination>().map_err(|err| setting_error(key, &err))?)),
+                None => Ok(None),
+            }
+        };
+        let signal_success = match &yaml["signal_success"] {
+            Yaml::BadValue => false,
+            x => x.as_bool().ok_or(not_allowed("signal_success"))?,
+        };
+        Ok(Self {
+            distance: CONFIG.distance.or(int("distance")?).unwrap_or(90),
+            distance_start: CONFIG.distance_start.or(int("distance_start")?),
+            distance_end: CONFIG.distance_end.or(int("distance_end")?),
+            on_fail: CONFIG
+                .on_fail
+                .or(CONFIG.fail.map(OnFail::from_flags))
+                .or(on_fail)
+                .or(fail)
+                .unwrap_or_default(),
+            retries: CONFIG.retries.or(fitting(int("retries")?, "retries")?).unwrap_or(3),
+            retry_delay: CONFIG.retry_delay.or(unsigned("retry_delay")?).unwrap_or(1),
+            retry_backoff: CONFIG.retry_backoff.or(unsigned("retry_backoff")?).unwrap_or(2),
+            on_success: CONFIG.on_success.to_owned().or(string("on_success")?),
+            on_failure: CONFIG.on_failure.to_owned().or(string("on_failure")?),
+            fail_on_code: CONFIG.fail_on_code.or(fitting(int("fail_on_code")?, "fail_on_code")?).unwrap_or(1),
+            // Flag can only enable it, so the file is used when it isn't given
+            signal_success: CONFIG.signal_success || signal_success,
+            pipe: CONFIG.pipe.map(|x| x.pipe).or(pipe).unwrap_or(Pipe::STDOUT),
+            pipe_to: CONFIG.pipe_to.to_owned().or(pipe_to).unwrap_or(PipeTo::STDERR),
+            file: CONFIG.file.to_owned().or(string("file")?.map(PathBuf::from)),
+            stdout_to: CONFIG.stdout_to.to_owned().or(destination("stdout_to")?),
+            stderr_to: CONFIG.stderr_to.to_owned().or(destination("stderr_to")?),
+        })
+    }
+}
+fn not_allowed(key: &str) -> String {
+    format!("Value of setting {} not allowed in the settings file", key)
+}
+/// Converts integer to the given type, if it fits it (e.g. retries can't be negative)
+fn fitting<T: TryFrom<i64>>(value: Option<i64>, key: &str) -> Result<Option<T>, String> {
+    match value {
+        Some(x) => T::try_from(x).map(Some).map_err(|_| not_allowed(key)),
+        None => Ok(None),
+    }
+}
+fn setting_error(key: &str, err: &str) -> String {
+    format!("Setting {} in the settings file: {}", key, err)
+}
+/// Returns current settings
+pub fn get() -> Arc<Settings> {
+    SETTINGS.read().unwrap().clone()
+}
+/// Reads settings file from the found files, if there is one, and replaces current settings
+/// Settings are kept if the file is broken
+pub fn load(conf_files: &[PathBuf]) -> Result<(), Box<dyn std::error::Error>> {
+    let file = conf_files
+        .iter()
+        .find(|x| !is_drop_in(x) && x.file_stem().is_some_and(|x| x == SETTINGS_FILE_STEM));
+    let yaml = match file {
+        Some(x) => {
+            debug!("Reading settings from {:?}", x);
+            YamlLoader::load_from_str(&fs::read_to_string(x)?)?
+                .into_iter()
+                .next()
+                .unwrap_or(Yaml::BadValue)
+        }
+        None => Yaml::BadValue,
+    };
+    let settings = Settings::new(&yaml)?;
+    *SETTINGS.write().unwrap() = Arc::new(settings);
+    Ok(())
+}
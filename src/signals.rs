@@ -0,0 +1,94 @@
+use nix::libc::c_int;
+use nix::sys::signal::{kill, sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use nix::unistd::Pid;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Time running scripts, hooks and services have to exit after SIGTERM or SIGINT, before they're killed
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+static RELOAD: AtomicBool = AtomicBool::new(false);
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    // Set once shutdown is noticed, shared by everything still running so the whole shutdown fits SHUTDOWN_TIMEOUT
+    static ref DEADLINE: Mutex<Option<Instant>> = Mutex::new(None);
+}
+
+extern "C" fn handle(signal: c_int) {
+    // Only atomics are touched, as almost nothing else is safe to call in a signal handler
+    if signal == Signal::SIGHUP as c_int {
+        RELOAD.store(true, Ordering::SeqCst);
+    } else {
+        SHUTDOWN.store(true, Ordering::SeqCst);
+    }
+}
+/// Installs handlers of SIGHUP (reload of the plan), SIGTERM and SIGINT (graceful shutdown)
+pub fn install() -> nix::Result<()> {
+    let action = SigAction::new(
+        SigHandler::Handler(handle),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    for signal in &[Signal::SIGHUP, Signal::SIGTERM, Signal::SIGINT] {
+        unsafe { sigaction(*signal, &action)? };
+    }
+    Ok(())
+}
+/// Returns whether reload was requested since the last call
+pub fn reload_requested() -> bool {
+    RELOAD.swap(false, Ordering::SeqCst)
+}
+pub fn shutdown_requested() -> bool {
+    SHUTDOWN.load(Ordering::SeqCst)
+}
+/// Returns time by which everything has to exit, once shutdown was requested
+pub fn shutdown_deadline() -> Option<Instant> {
+    if !shutdown_requested() {
+        return None;
+    }
+    let mut deadline = DEADLINE.lock().unwrap();
+    Some(*deadline.get_or_insert_with(|| Instant::now() + SHUTDOWN_TIMEOUT))
+}
+/// Sleeps for the given duration, wakes up early if reload or shutdown was requested
+pub fn sleep(duration: Duration) {
+    let start = Instant::now();
+    while !RELOAD.load(Ordering::SeqCst) && !shutdown_requested() {
+        let elapsed = start.elapsed();
+        if elapsed >= duration {
+            return;
+        }
+        thread::sleep(std::cmp::min(POLL_INTERVAL, duration - elapsed));
+    }
+}
+/// Watches a process, killing it if it's still running at the shutdown deadline
+/// Process stops being watched when the watchdog is dropped, i.e. once it's waited for
+#[derive(Debug)]
+pub struct Watchdog {
+    exited: Arc<AtomicBool>,
+}
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.exited.store(true, Ordering::SeqCst);
+    }
+}
+pub fn kill_on_shutdown(pid: u32) -> Watchdog {
+    let exited = Arc::new(AtomicBool::new(false));
+    let watched = exited.clone();
+    thread::spawn(move || {
+        while !watched.load(Ordering::SeqCst) {
+            if let Some(deadline) = shutdown_deadline() {
+                if Instant::now() >= deadline {
+                    warn!("Process {} didn't exit in time, killing it", pid);
+                    let _ = kill(Pid::from_raw(pid as i32), Signal::SIGKILL);
+                    return;
+                }
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    });
+    Watchdog { exited }
+}
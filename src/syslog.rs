@@ -0,0 +1,247 @@
+use crate::logger::{with_decision, Decision};
+use crate::{APP_NAME, CONFIG};
+use chrono::offset::Local;
+use log::{Level, Log, Metadata, Record};
+use pretty_env_logger::env_logger::{Builder, Env, Logger};
+use serde_json::Value;
+use std::io::{self, Write};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+
+const SYSLOG_SOCKET: &str = "/dev/log";
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+// Facility of system daemons
+const FACILITY_DAEMON: u8 = 3;
+
+/// Protocol spoken with the local log daemon
+#[derive(Debug)]
+enum Protocol {
+    // Native journald protocol, fields as KEY=value lines
+    JOURNALD,
+    // RFC 5424 messages, decision fields as structured data
+    RFC5424,
+}
+
+/// Logger sending records to journald if it's running, else to the syslog socket
+/// Decision fields are sent as ONTIME_* journal fields (e.g. ONTIME_EVENT) or structured data, when --syslog-sd-id is given
+#[derive(Debug)]
+pub struct SyslogLogger {
+    socket: UnixDatagram,
+    path: PathBuf,
+    protocol: Protocol,
+    // Private structured data ID, as registered IDs can't carry parameters of other names
+    sd_id: Option<String>,
+    // Used only to filter records according to RUST_LOG
+    filter: Logger,
+    hostname: String,
+}
+impl SyslogLogger {
+    pub fn new() -> io::Result<Self> {
+        let sd_id = CONFIG.syslog_sd_id.as_ref().map(|x| x.0.to_owned());
+        if Path::new(JOURNALD_SOCKET).exists() {
+            Self::with_socket(JOURNALD_SOCKET, Protocol::JOURNALD, sd_id)
+        } else {
+            Self::with_socket(SYSLOG_SOCKET, Protocol::RFC5424, sd_id)
+        }
+    }
+    fn with_socket<P: Into<PathBuf>>(
+        path: P,
+        protocol: Protocol,
+        sd_id: Option<String>,
+    ) -> io::Result<Self> {
+        let mut buffer = [0u8; 256];
+        let hostname = match nix::unistd::gethostname(&mut buffer) {
+            Ok(x) => x.to_string_lossy().into_owned(),
+            Err(_) => "-".into(),
+        };
+        Ok(Self {
+            socket: UnixDatagram::unbound()?,
+            path: path.into(),
+            protocol,
+            sd_id,
+            filter: Builder::from_env(Env::default().default_filter_or("info")).build(),
+            hostname,
+        })
+    }
+    fn format_journald(&self, record: &Record, decision: Option<&Decision>) -> Vec<u8> {
+        let mut message = Vec::new();
+        journald_field(&mut message, "MESSAGE", &record.args().to_string());
+        journald_field(&mut message, "PRIORITY", &severity(record.level()).to_string());
+        journald_field(&mut message, "SYSLOG_IDENTIFIER", APP_NAME);
+        journald_field(&mut message, "ONTIME_TARGET", record.target());
+        if let Some(decision) = decision {
+            journald_field(&mut message, "ONTIME_DECISION", decision.kind());
+            for (name, value) in decision.fields().iter().filter(|(_, x)| !x.is_null()) {
+                let name = format!("ONTIME_{}", name.to_uppercase());
+                journald_field(&mut message, &name, &field_string(value));
+            }
+        }
+        message
+    }
+    fn format_rfc5424(&self, record: &Record, decision: Option<&Decision>) -> Vec<u8> {
+        let priority = FACILITY_DAEMON * 8 + severity(record.level());
+        let msg_id = decision.map_or("-", |x| x.kind());
+        let structured_data = match (decision, &self.sd_id) {
+            (Some(decision), Some(sd_id)) => {
+                let params = decision
+                    .fields()
+                    .iter()
+                    .filter(|(_, x)| !x.is_null())
+                    .map(|(name, value)| format!(" {}=\"{}\"", name, sd_escape(&field_string(value))))
+                    .collect::<String>();
+                format!("[{}{}]", sd_id, params)
+            }
+            _ => "-".to_owned(),
+        };
+        format!(
+            "<{}>1 {} {} {} {} {} {} {}",
+            priority,
+            Local::now().to_rfc3339(),
+            self.hostname,
+            APP_NAME,
+            std::process::id(),
+            msg_id,
+            structured_data,
+            record.args()
+        )
+        .into_bytes()
+    }
+}
+impl Log for SyslogLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+    fn log(&self, record: &Record) {
+        if !self.filter.matches(record) {
+            return;
+        }
+        let message = with_decision(|decision| match self.protocol {
+            Protocol::JOURNALD => self.format_journald(record, decision),
+            Protocol::RFC5424 => self.format_rfc5424(record, decision),
+        });
+        // Logging must not fail, so fall back to standard error when the daemon isn't listening
+        if self.socket.send_to(&message, &self.path).is_err() {
+            let _ = writeln!(io::stderr(), "{} {}", record.level(), record.args());
+        }
+    }
+    fn flush(&self) {}
+}
+lazy_static! {
+    // Used for output of the executed commands, so it goes to syslog even if ontime logs elsewhere
+    static ref OUTPUT_LOGGER: Option<SyslogLogger> = match SyslogLogger::new() {
+        Ok(x) => Some(x),
+        Err(err) => {
+            error!("Couldn't open syslog for the output of commands: {}", err);
+            None
+        }
+    };
+}
+/// Sends a line of the executed command's output to syslog, standard error with warning severity
+pub fn send_output(event: &str, stream: &str, line: &str) {
+    let logger = match OUTPUT_LOGGER.as_ref() {
+        Some(x) => x,
+        None => return,
+    };
+    let level = match stream {
+        "stderr" => Level::Warn,
+        _ => Level::Info,
+    };
+    Decision::new("command_output")
+        .event(event)
+        .field("stream", stream)
+        .log_to(logger, level, line);
+}
+/// Syslog severity of the log level
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+fn field_string(value: &Value) -> String {
+    match value {
+        Value::String(x) => x.to_owned(),
+        x => x.to_string(),
+    }
+}
+/// Appends journal field, values containing a newline are written in the binary form
+fn journald_field(message: &mut Vec<u8>, name: &str, value: &str) {
+    message.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        message.push(b'\n');
+        message.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        message.push(b'=');
+    }
+    message.extend_from_slice(value.as_bytes());
+    message.push(b'\n');
+}
+/// Escapes characters not allowed in structured data parameter values
+fn sd_escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace(']', "\\]")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SdId;
+
+    // Sends the decision through a logger with the given structured data ID, returns the received line split into its parts
+    fn send(sd_id: Option<&str>) -> Vec<String> {
+        let path = std::env::temp_dir().join(format!(
+            "ontime-syslog-{}-{}.sock",
+            std::process::id(),
+            sd_id.is_some()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixDatagram::bind(&path).unwrap();
+        let logger =
+            SyslogLogger::with_socket(&path, Protocol::RFC5424, sd_id.map(str::to_owned)).unwrap();
+        Decision::new("command_output")
+            .event("backup")
+            .field("stream", "stderr")
+            .log_to(&logger, Level::Warn, "disk \"full\"");
+        let mut buffer = [0u8; 1024];
+        let length = listener.recv(&mut buffer).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let line = String::from_utf8_lossy(&buffer[..length]).into_owned();
+        line.splitn(8, ' ').map(str::to_owned).collect()
+    }
+
+    #[test]
+    fn sends_rfc5424_line_with_decision_fields() {
+        // Enterprise number reserved for documentation (RFC 5612)
+        let parts = send(Some("ontime@32473"));
+        // Facility daemon (3) and severity warning (4)
+        assert_eq!(parts[0], "<28>1");
+        assert!(chrono::DateTime::parse_from_rfc3339(&parts[1]).is_ok());
+        assert_eq!(parts[3], APP_NAME);
+        assert_eq!(parts[4], std::process::id().to_string());
+        assert_eq!(parts[5], "command_output");
+        assert_eq!(
+            parts[6..].join(" "),
+            "[ontime@32473 event=\"backup\" stream=\"stderr\"] disk \"full\""
+        );
+    }
+    #[test]
+    fn sends_rfc5424_line_without_structured_data_without_sd_id() {
+        let parts = send(None);
+        assert_eq!(parts[5], "command_output");
+        assert_eq!(parts[6..].join(" "), "- disk \"full\"");
+    }
+    #[test]
+    fn accepts_only_private_sd_ids() {
+        assert!("ontime@32473".parse::<SdId>().is_ok());
+        assert!("ontime@32473.1".parse::<SdId>().is_ok());
+        assert!("meta".parse::<SdId>().is_err());
+        assert!("ontime@".parse::<SdId>().is_err());
+        assert!("ontime@abc".parse::<SdId>().is_err());
+        assert!("on time@32473".parse::<SdId>().is_err());
+        assert!("on=time@32473".parse::<SdId>().is_err());
+    }
+}
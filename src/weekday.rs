@@ -14,3 +14,76 @@ pub fn chrono_to_string(weekday: &Weekday) -> Cow<'static, str> {
     }
     .into()
 }
+/// Parses a key of main.yml naming days, returns the days it covers or None if it doesn't name days
+/// key := item ("," item)*
+/// item := day | day "-" day | "weekdays" | "weekend" | "everyday"
+/// day := full (monday) or short (mon) name, in any case
+/// Ranges can wrap around the end of the week, e.g. friday-monday
+pub fn parse_days(key: &str) -> Option<Vec<Weekday>> {
+    let mut days = Vec::new();
+    for item in key.split(',').map(|x| x.trim().to_lowercase()) {
+        match item.as_str() {
+            "weekdays" => days.extend(range(Weekday::Mon, Weekday::Fri)),
+            "weekend" => days.extend(range(Weekday::Sat, Weekday::Sun)),
+            "everyday" => days.extend(range(Weekday::Mon, Weekday::Sun)),
+            x => {
+                let mut bounds = x.splitn(2, '-');
+                let first = bounds.next()?.trim().parse::<Weekday>().ok()?;
+                match bounds.next() {
+                    Some(last) => days.extend(range(first, last.trim().parse::<Weekday>().ok()?)),
+                    None => days.push(first),
+                }
+            }
+        }
+    }
+    let mut unique = Vec::new();
+    for day in days {
+        if !unique.contains(&day) {
+            unique.push(day);
+        }
+    }
+    Some(unique)
+}
+fn range(first: Weekday, last: Weekday) -> Vec<Weekday> {
+    let mut days = vec![first];
+    let mut day = first;
+    while day != last {
+        day = day.succ();
+        days.push(day);
+    }
+    days
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Weekday::*;
+
+    #[test]
+    fn parses_names() {
+        assert_eq!(parse_days("monday"), Some(vec![Mon]));
+        assert_eq!(parse_days("Fri"), Some(vec![Fri]));
+        assert_eq!(parse_days("someday"), None);
+        assert_eq!(parse_days(""), None);
+    }
+    #[test]
+    fn parses_groups() {
+        assert_eq!(parse_days("weekdays"), Some(vec![Mon, Tue, Wed, Thu, Fri]));
+        assert_eq!(parse_days("weekend"), Some(vec![Sat, Sun]));
+        assert_eq!(parse_days("everyday").map(|x| x.len()), Some(7));
+    }
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(parse_days("monday-wednesday"), Some(vec![Mon, Tue, Wed]));
+        assert_eq!(parse_days("friday-monday"), Some(vec![Fri, Sat, Sun, Mon]));
+        assert_eq!(parse_days("sun-sun"), Some(vec![Sun]));
+        assert_eq!(parse_days("monday-"), None);
+        assert_eq!(parse_days("monday-someday"), None);
+    }
+    #[test]
+    fn parses_lists() {
+        assert_eq!(parse_days("mon, wed,fri"), Some(vec![Mon, Wed, Fri]));
+        assert_eq!(parse_days("weekend,fri-sun,mon"), Some(vec![Sat, Sun, Fri, Mon]));
+        assert_eq!(parse_days("mon,templates"), None);
+    }
+}